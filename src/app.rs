@@ -1,6 +1,19 @@
+use crate::config::Config;
+use crate::keymap::{KeyContext, Keymap};
+use crate::state::{RecentRoom, State};
+use crate::theme::Theme;
+use crate::util::fuzzy_match;
+use ratatui::layout::Rect;
+use regex::Regex;
+use std::cmp::Reverse;
 use std::collections::VecDeque;
+use std::io::Error;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
+use tracing::warn;
+use zeroize::Zeroize;
 
 #[derive(Debug)]
 pub enum CurrentScreen {
@@ -9,14 +22,16 @@ pub enum CurrentScreen {
     CreatePassword,
     Join,
     RoomSelect,
+    RecentRooms,
     PasswordCheck,
     Chat,
     Exiting,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum CurrentSelection {
     Create,
     Join,
+    Recent,
 }
 #[derive(Debug)]
 pub enum JoinRoomInput {
@@ -32,6 +47,125 @@ pub enum CreateRoomInput {
 pub enum ChatRoomMode {
     Input,
     Normal,
+    MemberList,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemberRole {
+    Owner,
+    Member,
+}
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub role: MemberRole,
+    // None when the member was already in the room before we joined
+    pub joined_at: Option<Instant>,
+    pub last_active: Option<Instant>,
+    pub muted: bool,
+}
+
+impl Member {
+    pub fn new(name: String, role: MemberRole, joined_at: Option<Instant>) -> Self {
+        Member {
+            name,
+            role,
+            joined_at,
+            last_active: joined_at,
+            muted: false,
+        }
+    }
+}
+// State the chat task shares with the UI, every connection gets a clone of it
+#[derive(Debug, Clone)]
+pub struct RoomShared {
+    pub chat_room_record: Arc<Mutex<VecDeque<String>>>,
+    // Messages ever pushed to chat_room_record, it keeps counting once old ones are dropped
+    pub record_total: Arc<Mutex<usize>>,
+    pub chat_room_member: Arc<Mutex<Vec<Member>>>,
+    pub abandon: Arc<Mutex<bool>>,
+    pub kick_reason: Arc<Mutex<Option<String>>>,
+    pub mention_count: Arc<Mutex<u32>>,
+    pub room_topic: Arc<Mutex<String>>,
+    pub highlight_rules: Arc<Vec<Regex>>,
+    // Ignored users on this server, kept across rooms. Their messages are hidden and
+    // never count as mentions.
+    pub ignored_users: Arc<Mutex<Vec<String>>>,
+}
+
+impl RoomShared {
+    pub fn new(highlight_rules: Arc<Vec<Regex>>, ignored_users: Arc<Mutex<Vec<String>>>) -> Self {
+        RoomShared {
+            chat_room_record: Arc::new(Mutex::new(VecDeque::new())),
+            record_total: Arc::new(Mutex::new(0)),
+            chat_room_member: Arc::new(Mutex::new(vec![])),
+            abandon: Arc::new(Mutex::new(false)),
+            kick_reason: Arc::new(Mutex::new(None)),
+            mention_count: Arc::new(Mutex::new(0)),
+            room_topic: Arc::new(Mutex::new(String::new())),
+            highlight_rules,
+            ignored_users,
+        }
+    }
+
+    pub async fn push_record(&self, msg: String, record_size: usize) {
+        let mut room_record_handle = self.chat_room_record.lock().await;
+        room_record_handle.push_back(msg);
+        if room_record_handle.len() > record_size {
+            room_record_handle.pop_front();
+        }
+        // Counted under the record lock so a snapshot of both always agrees
+        *self.record_total.lock().await += 1;
+    }
+}
+// Where the clickable parts of the last frame were drawn, filled in by ui()
+#[derive(Debug, Default)]
+pub struct ClickAreas {
+    pub entry_options: Vec<(Rect, CurrentSelection)>,
+    // Index into App::filtered_rooms
+    pub rooms: Vec<(Rect, usize)>,
+    pub recent_rooms: Vec<(Rect, usize)>,
+    pub members: Vec<(Rect, usize)>,
+    pub chat_history: Option<Rect>,
+    pub chat_input: Option<Rect>,
+}
+// The room the PasswordCheck screen joins and the screen its Back key returns to
+#[derive(Debug)]
+pub struct PasswordTarget {
+    pub room_id: String,
+    // Empty when the room was joined by ID, the server sends the name back
+    pub room_name: String,
+    pub invite_code: Option<String>,
+    pub back_to: CurrentScreen,
+}
+#[derive(Debug)]
+pub struct Completion {
+    pub start: usize,
+    pub candidates: Vec<String>,
+    pub idx: usize,
+}
+#[derive(Debug, Clone)]
+pub struct Room {
+    pub id: String,
+    pub name: String,
+    pub has_password: bool,
+    // The fields below are only sent by newer servers
+    pub member_count: Option<u32>,
+    pub creator: Option<String>,
+    pub created_at: Option<u64>,
+    pub topic: Option<String>,
+}
+#[derive(Debug, PartialEq)]
+pub enum RoomSort {
+    ServerOrder,
+    Name,
+    Members,
+    Newest,
+}
+#[derive(Debug, PartialEq)]
+pub enum RoomKindFilter {
+    All,
+    OpenOnly,
+    PasswordOnly,
 }
 #[derive(Debug)]
 pub enum CreateRoomError {
@@ -41,6 +175,7 @@ pub enum CreateRoomError {
     InvalidRoomNameLength,
     ServerError,
     InvalidPasswordChar,
+    UsernameTaken,
 }
 #[derive(Debug)]
 pub enum JoinRoomError {
@@ -50,90 +185,340 @@ pub enum JoinRoomError {
     RoomNotFound,
     GetRoomListFailed,
     WrongPassword,
+    // An empty password was sent to a room that has one
+    PasswordRequired,
     ZeroRooms,
+    UsernameTaken,
+    // Invite link made on another server, holds that server's address
+    OtherServer(String),
 }
 
 #[derive(Debug)]
 pub struct App {
     pub input: String,
-    pub chat_room_record: Arc<Mutex<VecDeque<String>>>,
-    pub chat_room_member: Arc<Mutex<Vec<String>>>,
+    pub room: RoomShared,
     pub record_size: u32,
     pub current_screen: CurrentScreen,
     pub exiting: bool,
     pub room_id: String,
-    pub room_lst: Vec<(String, String, bool)>,
+    pub room_lst: Vec<Room>,
     pub room_idx: usize,
+    pub room_filter: String,
+    pub room_filter_editing: bool,
+    pub room_kind_filter: RoomKindFilter,
+    pub room_sort: RoomSort,
     pub username: String,
     pub current_selection: CurrentSelection,
     pub join_room_input: JoinRoomInput,
     pub chat_room_mode: ChatRoomMode,
     pub msg_pipe: Option<tokio::sync::mpsc::Sender<String>>,
-    pub abandon: Arc<Mutex<bool>>,
+    pub entry_notice: Option<String>,
     pub create_room_error: Option<CreateRoomError>,
     pub join_room_error: Option<JoinRoomError>,
     pub room_name: String,
     pub password: String,
     pub password_prompt: bool,
+    pub private_room: bool,
+    pub invite_link: Option<String>,
     pub create_room_input: CreateRoomInput,
     pub check_passwork: String,
+    pub password_target: Option<PasswordTarget>,
+    pub show_password: bool,
+    pub member_idx: usize,
+    pub notified_mentions: u32,
+    pub completion: Option<Completion>,
+    pub config: Config,
+    pub theme: Theme,
+    pub keymap: Keymap,
+    pub config_path: Option<PathBuf>,
+    pub state: State,
+    pub state_path: Option<PathBuf>,
+    pub recent_idx: usize,
+    pub show_help: bool,
+    pub help_scroll: u16,
+    // Messages hidden below the bottom of the chat history, 0 follows the newest
+    pub chat_scroll: usize,
+    // RoomShared::record_total when chat_scroll was last adjusted for new messages
+    pub seen_record_total: usize,
+    pub click_areas: ClickAreas,
 }
 
 impl App {
     pub fn new() -> Self {
         App {
             input: String::new(),
-            chat_room_record: Arc::new(Mutex::new(VecDeque::new())),
-            chat_room_member: Arc::new(Mutex::new(vec![])),
+            room: RoomShared::new(Arc::new(vec![]), Arc::new(Mutex::new(vec![]))),
             record_size: 100,
             current_screen: CurrentScreen::Entry,
             exiting: false,
             room_id: String::new(),
             room_lst: vec![],
             room_idx: 0,
+            room_filter: String::new(),
+            room_filter_editing: false,
+            room_kind_filter: RoomKindFilter::All,
+            room_sort: RoomSort::ServerOrder,
             username: String::new(),
             current_selection: CurrentSelection::Create,
             join_room_input: JoinRoomInput::Username,
             chat_room_mode: ChatRoomMode::Normal,
             msg_pipe: None,
-            abandon: Arc::new(Mutex::new(false)),
+            entry_notice: None,
             create_room_error: None,
             join_room_error: None,
             room_name: String::new(),
             password: String::new(),
             password_prompt: false,
+            private_room: false,
+            invite_link: None,
             create_room_input: CreateRoomInput::Username,
             check_passwork: String::new(),
+            password_target: None,
+            show_password: false,
+            member_idx: 0,
+            notified_mentions: 0,
+            completion: None,
+            config: Config::default(),
+            theme: Theme::default(),
+            keymap: Keymap::default(),
+            config_path: None,
+            state: State::default(),
+            state_path: None,
+            recent_idx: 0,
+            show_help: false,
+            help_scroll: 0,
+            chat_scroll: 0,
+            seen_record_total: 0,
+            click_areas: ClickAreas::default(),
+        }
+    }
+
+    // Which keys apply right now, ignoring the help overlay
+    pub fn key_context(&self) -> KeyContext {
+        match self.current_screen {
+            CurrentScreen::Entry | CurrentScreen::Exiting => KeyContext::Entry,
+            CurrentScreen::Create if self.password_prompt => KeyContext::CreatePrompt,
+            CurrentScreen::Create | CurrentScreen::Join => KeyContext::Form,
+            CurrentScreen::CreatePassword | CurrentScreen::PasswordCheck => {
+                KeyContext::PasswordForm
+            }
+            CurrentScreen::RoomSelect if self.room_filter_editing => KeyContext::Form,
+            CurrentScreen::RoomSelect => KeyContext::RoomList,
+            CurrentScreen::RecentRooms => KeyContext::RecentRooms,
+            CurrentScreen::Chat if self.exiting => KeyContext::ExitConfirm,
+            CurrentScreen::Chat => match self.chat_room_mode {
+                ChatRoomMode::Normal => KeyContext::ChatNormal,
+                ChatRoomMode::MemberList => KeyContext::MemberList,
+                ChatRoomMode::Input => KeyContext::ChatInput,
+            },
         }
     }
 
-    pub fn enter_room(&mut self, room_id: String) {
+    pub fn enter_room(&mut self, room_id: String, server: &str, invite_code: Option<String>) {
+        self.state.last_username = Some(self.username.clone());
+        self.state.remember_room(RecentRoom {
+            id: room_id.clone(),
+            name: self.room_name.clone(),
+            server: server.to_string(),
+            invite_code,
+        });
+        // Failing to remember recent rooms shouldn't get in the way of chatting
+        if let Some(path) = &self.state_path {
+            if let Err(e) = self.state.save(path) {
+                warn!(error = %e, path = %path.display(), "failed to save state");
+            }
+        }
+
         self.room_id = room_id;
         self.current_screen = CurrentScreen::Chat;
+        self.password.zeroize();
+        self.check_passwork.zeroize();
+        self.password_target = None;
+        self.show_password = false;
     }
 
     pub fn reinitialize(&mut self) {
         self.input = String::new();
-        self.chat_room_record = Arc::new(Mutex::new(VecDeque::new()));
-        self.chat_room_member = Arc::new(Mutex::new(vec![]));
-        self.record_size = 100;
+        self.room = RoomShared::new(
+            self.room.highlight_rules.clone(),
+            self.room.ignored_users.clone(),
+        );
+        self.record_size = self.config.history_size;
         self.current_screen = CurrentScreen::Entry;
         self.exiting = false;
         self.room_id = String::new();
-        self.username = String::new();
+        self.username = self.default_username();
         self.current_selection = CurrentSelection::Create;
         self.join_room_input = JoinRoomInput::Username;
         self.chat_room_mode = ChatRoomMode::Normal;
         self.msg_pipe = None;
-        self.abandon = Arc::new(Mutex::new(false));
         self.create_room_error = None;
         self.join_room_error = None;
         self.room_idx = 0;
         self.room_lst = vec![];
+        self.room_filter = String::new();
+        self.room_filter_editing = false;
+        self.room_kind_filter = RoomKindFilter::All;
+        self.room_sort = RoomSort::ServerOrder;
         self.room_name = String::new();
-        self.password = String::new();
+        self.password.zeroize();
         self.password_prompt = false;
+        self.private_room = false;
+        self.invite_link = None;
         self.create_room_input = CreateRoomInput::Username;
-        self.check_passwork = String::new();
+        self.check_passwork.zeroize();
+        self.password_target = None;
+        self.show_password = false;
+        self.member_idx = 0;
+        self.recent_idx = 0;
+        self.notified_mentions = 0;
+        self.completion = None;
+        self.show_help = false;
+        self.help_scroll = 0;
+        self.chat_scroll = 0;
+        self.seen_record_total = 0;
+    }
+
+    // The name used last time wins over the one set in the config
+    pub fn default_username(&self) -> String {
+        self.state
+            .last_username
+            .clone()
+            .or_else(|| self.config.username.clone())
+            .unwrap_or_default()
+    }
+
+    pub async fn clear_mentions(&mut self) {
+        *self.room.mention_count.lock().await = 0;
+        self.notified_mentions = 0;
+    }
+
+    // Rooms shown on the RoomSelect screen after applying the filter and sort order
+    pub fn filtered_rooms(&self) -> Vec<&Room> {
+        let mut rooms = self
+            .room_lst
+            .iter()
+            .filter(|room| match self.room_kind_filter {
+                RoomKindFilter::All => true,
+                RoomKindFilter::OpenOnly => !room.has_password,
+                RoomKindFilter::PasswordOnly => room.has_password,
+            })
+            .filter(|room| {
+                fuzzy_match(&room.name, &self.room_filter) || room.id.contains(&self.room_filter)
+            })
+            .collect::<Vec<&Room>>();
+
+        match self.room_sort {
+            RoomSort::ServerOrder => {}
+            RoomSort::Name => rooms.sort_by_key(|room| room.name.to_lowercase()),
+            // Rooms from older servers have no member count or creation time and go last
+            RoomSort::Members => {
+                rooms.sort_by_key(|room| (Reverse(room.member_count), room.name.to_lowercase()))
+            }
+            RoomSort::Newest => {
+                rooms.sort_by_key(|room| (Reverse(room.created_at), room.name.to_lowercase()))
+            }
+        }
+        rooms
+    }
+
+    pub fn selected_room(&self) -> Option<Room> {
+        self.filtered_rooms()
+            .get(self.room_idx)
+            .map(|room| (*room).clone())
+    }
+
+    pub async fn is_muted(&self) -> bool {
+        self.room
+            .chat_room_member
+            .lock()
+            .await
+            .iter()
+            .any(|member| member.name == self.username && member.muted)
+    }
+
+    pub async fn is_owner(&self) -> bool {
+        self.room
+            .chat_room_member
+            .lock()
+            .await
+            .iter()
+            .any(|member| member.name == self.username && member.role == MemberRole::Owner)
+    }
+
+    // Ignored users are remembered per server so the same name on another server is unaffected
+    pub async fn set_ignored(
+        &mut self,
+        server: &str,
+        name: &str,
+        ignored: bool,
+    ) -> Result<(), Error> {
+        let mut ignored_users = self.room.ignored_users.lock().await;
+        let pos = ignored_users.iter().position(|x| x == name);
+        match (pos, ignored) {
+            (None, true) => ignored_users.push(name.to_string()),
+            (Some(pos), false) => {
+                ignored_users.remove(pos);
+            }
+            _ => return Ok(()),
+        }
+
+        if ignored_users.is_empty() {
+            self.config.ignore.remove(server);
+        } else {
+            self.config
+                .ignore
+                .insert(server.to_string(), ignored_users.clone());
+        }
+        drop(ignored_users);
+        match &self.config_path {
+            Some(path) => self.config.save_ignore(path),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn push_local_msg(&self, msg: String) {
+        self.room.push_record(msg, self.record_size as usize).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(name: &str, member_count: Option<u32>, created_at: Option<u64>) -> Room {
+        Room {
+            id: name.to_lowercase(),
+            name: name.to_string(),
+            has_password: false,
+            member_count,
+            creator: None,
+            created_at,
+            topic: None,
+        }
+    }
+
+    fn sorted_names(app: &App) -> Vec<&str> {
+        app.filtered_rooms()
+            .iter()
+            .map(|room| room.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn sorts_on_room_fields_with_missing_ones_last() {
+        let mut app = App::new();
+        app.room_lst = vec![
+            room("Old", None, None),
+            room("Busy", Some(9), Some(100)),
+            room("Fresh", Some(2), Some(300)),
+            room("Quiet", Some(2), Some(200)),
+        ];
+
+        assert_eq!(sorted_names(&app), ["Old", "Busy", "Fresh", "Quiet"]);
+        app.room_sort = RoomSort::Members;
+        assert_eq!(sorted_names(&app), ["Busy", "Fresh", "Quiet", "Old"]);
+        app.room_sort = RoomSort::Newest;
+        assert_eq!(sorted_names(&app), ["Fresh", "Quiet", "Busy", "Old"]);
     }
 }
@@ -0,0 +1,301 @@
+use std::str::FromStr;
+
+pub const COMMAND_NAMES: &[&str] = &[
+    "msg", "topic", "kick", "ban", "mute", "unmute", "transfer", "password", "ignore", "unignore",
+    "ignored",
+];
+
+// Usage and description of each command, for the instruction panel and the help overlay
+pub const COMMAND_HELP: &[(&str, &str)] = &[
+    ("/msg <user> <text>", "send a private message"),
+    ("/topic <text>", "set the room topic"),
+    ("/topic", "clear the room topic"),
+    ("/kick <user>", "remove a member, owner only"),
+    ("/ban <user>", "remove a member for good, owner only"),
+    ("/mute <user>", "stop a member from talking, owner only"),
+    (
+        "/unmute <user>",
+        "let a muted member talk again, owner only",
+    ),
+    ("/transfer <user>", "hand the room over, owner only"),
+    ("/password set <password>", "protect the room, owner only"),
+    ("/password clear", "remove the room password, owner only"),
+    ("/ignore <user>", "hide a user's messages"),
+    ("/unignore <user>", "show a user's messages again"),
+    ("/ignored", "list ignored users"),
+];
+
+#[derive(Debug)]
+pub enum ChatCommand {
+    PrivateMsg { target: String, text: String },
+    Topic { topic: String },
+    Moderate { action: ModAction, target: String },
+    // None removes the room password
+    Password { password: Option<String> },
+    Ignore { target: String, ignored: bool },
+    ListIgnored,
+}
+
+impl ChatCommand {
+    // Local commands are handled by the client and never reach the server
+    pub fn is_local(&self) -> bool {
+        matches!(self, ChatCommand::Ignore { .. } | ChatCommand::ListIgnored)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModAction {
+    Kick,
+    Ban,
+    Mute,
+    Unmute,
+    Transfer,
+}
+
+impl ModAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModAction::Kick => "kick",
+            ModAction::Ban => "ban",
+            ModAction::Mute => "mute",
+            ModAction::Unmute => "unmute",
+            ModAction::Transfer => "transfer",
+        }
+    }
+}
+
+impl FromStr for ModAction {
+    type Err = ChatCommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kick" => Ok(ModAction::Kick),
+            "ban" => Ok(ModAction::Ban),
+            "mute" => Ok(ModAction::Mute),
+            "unmute" => Ok(ModAction::Unmute),
+            "transfer" => Ok(ModAction::Transfer),
+            _ => Err(ChatCommandError::UnknownCommand(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ChatCommandError {
+    UnknownCommand(String),
+    Usage(&'static str),
+}
+
+impl ChatCommandError {
+    pub fn message(&self) -> String {
+        match self {
+            ChatCommandError::UnknownCommand(name) => format!("Unknown command: /{}", name),
+            ChatCommandError::Usage(usage) => format!("Usage: {}", usage),
+        }
+    }
+}
+
+// Returns None when the input is a plain chat message rather than a slash command
+pub fn parse_chat_command(input: &str) -> Option<Result<ChatCommand, ChatCommandError>> {
+    let body = input.strip_prefix('/')?;
+    let (name, args) = match body.split_once(' ') {
+        Some((name, args)) => (name, args.trim_start()),
+        None => (body, ""),
+    };
+
+    let command = match name {
+        "msg" => match args.split_once(' ') {
+            Some((target, text)) if !text.trim().is_empty() => Ok(ChatCommand::PrivateMsg {
+                target: target.to_string(),
+                text: text.trim().to_string(),
+            }),
+            _ => Err(ChatCommandError::Usage("/msg <user> <text>")),
+        },
+        // Without text the topic is cleared
+        "topic" => Ok(ChatCommand::Topic {
+            topic: args.trim().to_string(),
+        }),
+        "password" => match args.trim().split_once(' ') {
+            Some(("set", password)) if !password.trim().is_empty() => Ok(ChatCommand::Password {
+                password: Some(password.trim().to_string()),
+            }),
+            None if args.trim() == "clear" => Ok(ChatCommand::Password { password: None }),
+            _ => Err(ChatCommandError::Usage(
+                "/password set <password> | /password clear",
+            )),
+        },
+        "ignore" | "unignore" => match args.trim() {
+            "" if name == "ignore" => Err(ChatCommandError::Usage("/ignore <user>")),
+            "" => Err(ChatCommandError::Usage("/unignore <user>")),
+            target => Ok(ChatCommand::Ignore {
+                target: target.to_string(),
+                ignored: name == "ignore",
+            }),
+        },
+        "ignored" => Ok(ChatCommand::ListIgnored),
+        // Whatever is left is either a moderation command or unknown
+        _ => ModAction::from_str(name).and_then(|action| match args.trim() {
+            "" => Err(ChatCommandError::Usage(match action {
+                ModAction::Kick => "/kick <user>",
+                ModAction::Ban => "/ban <user>",
+                ModAction::Mute => "/mute <user>",
+                ModAction::Unmute => "/unmute <user>",
+                ModAction::Transfer => "/transfer <user>",
+            })),
+            target => Ok(ChatCommand::Moderate {
+                action,
+                target: target.to_string(),
+            }),
+        }),
+    };
+
+    Some(command)
+}
+
+// Finds the word under completion at the end of the input and every candidate for it.
+// Returns the byte offset where that word starts together with the sorted candidates.
+pub fn completion_candidates(input: &str, members: &[String]) -> Option<(usize, Vec<String>)> {
+    let start = input.rfind(' ').map_or(0, |idx| idx + 1);
+    let word = &input[start..];
+
+    let mut candidates = if start == 0 && word.starts_with('/') {
+        COMMAND_NAMES
+            .iter()
+            .filter(|name| name.starts_with(&word[1..]))
+            .map(|name| format!("/{} ", name))
+            .collect::<Vec<String>>()
+    } else if let Some(partial) = word.strip_prefix('@') {
+        members
+            .iter()
+            .filter(|name| name.starts_with(partial))
+            .map(|name| format!("@{}", name))
+            .collect::<Vec<String>>()
+    } else if !word.is_empty() {
+        members
+            .iter()
+            .filter(|name| name.starts_with(word))
+            .cloned()
+            .collect::<Vec<String>>()
+    } else {
+        vec![]
+    };
+
+    if candidates.is_empty() {
+        return None;
+    }
+    candidates.sort();
+    Some((start, candidates))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage_of(input: &str) -> Option<&'static str> {
+        match parse_chat_command(input) {
+            Some(Err(ChatCommandError::Usage(usage))) => Some(usage),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn plain_messages_are_not_commands() {
+        assert!(parse_chat_command("hello /msg").is_none());
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        assert!(matches!(
+            parse_chat_command("/shrug"),
+            Some(Err(ChatCommandError::UnknownCommand(name))) if name == "shrug"
+        ));
+        assert!(matches!(
+            parse_chat_command("/ kick bob"),
+            Some(Err(ChatCommandError::UnknownCommand(name))) if name.is_empty()
+        ));
+    }
+
+    #[test]
+    fn msg_needs_a_target_and_text() {
+        assert_eq!(usage_of("/msg"), Some("/msg <user> <text>"));
+        assert_eq!(usage_of("/msg bob"), Some("/msg <user> <text>"));
+        assert_eq!(usage_of("/msg bob   "), Some("/msg <user> <text>"));
+        assert!(matches!(
+            parse_chat_command("/msg bob  hi there "),
+            Some(Ok(ChatCommand::PrivateMsg { target, text })) if target == "bob" && text == "hi there"
+        ));
+    }
+
+    #[test]
+    fn completion_candidates_are_sorted() {
+        let members = ["zoe", "bob", "bea", "al"].map(String::from);
+
+        assert_eq!(
+            completion_candidates("/u", &members),
+            Some((0, vec!["/unignore ".to_string(), "/unmute ".to_string()]))
+        );
+        assert_eq!(
+            completion_candidates("hi @b", &members),
+            Some((3, vec!["@bea".to_string(), "@bob".to_string()]))
+        );
+        assert_eq!(
+            completion_candidates("/msg b", &members),
+            Some((5, vec!["bea".to_string(), "bob".to_string()]))
+        );
+        assert_eq!(completion_candidates("hi ", &members), None);
+        assert_eq!(completion_candidates("/x", &members), None);
+    }
+
+    #[test]
+    fn bare_topic_clears_it() {
+        assert!(matches!(
+            parse_chat_command("/topic"),
+            Some(Ok(ChatCommand::Topic { topic })) if topic.is_empty()
+        ));
+        assert!(matches!(
+            parse_chat_command("/topic  clear "),
+            Some(Ok(ChatCommand::Topic { topic })) if topic == "clear"
+        ));
+    }
+
+    #[test]
+    fn moderation_commands_need_a_target() {
+        assert_eq!(usage_of("/kick"), Some("/kick <user>"));
+        assert_eq!(usage_of("/transfer  "), Some("/transfer <user>"));
+        assert!(matches!(
+            parse_chat_command("/mute bob"),
+            Some(Ok(ChatCommand::Moderate { action: ModAction::Mute, target })) if target == "bob"
+        ));
+        assert!(matches!("ban".parse::<ModAction>(), Ok(ModAction::Ban)));
+        assert!("kicks".parse::<ModAction>().is_err());
+    }
+
+    #[test]
+    fn password_needs_set_or_clear() {
+        let usage = Some("/password set <password> | /password clear");
+        assert_eq!(usage_of("/password"), usage);
+        assert_eq!(usage_of("/password set"), usage);
+        assert_eq!(usage_of("/password reset hunter2"), usage);
+        assert!(matches!(
+            parse_chat_command("/password set hunter2"),
+            Some(Ok(ChatCommand::Password { password: Some(password) })) if password == "hunter2"
+        ));
+        assert!(matches!(
+            parse_chat_command("/password clear"),
+            Some(Ok(ChatCommand::Password { password: None }))
+        ));
+    }
+
+    #[test]
+    fn ignore_commands() {
+        assert_eq!(usage_of("/ignore"), Some("/ignore <user>"));
+        assert_eq!(usage_of("/unignore"), Some("/unignore <user>"));
+        assert!(matches!(
+            parse_chat_command("/unignore bob"),
+            Some(Ok(ChatCommand::Ignore { target, ignored: false })) if target == "bob"
+        ));
+        assert!(matches!(
+            parse_chat_command("/ignored"),
+            Some(Ok(ChatCommand::ListIgnored))
+        ));
+    }
+}
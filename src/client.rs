@@ -1,125 +1,220 @@
-use crate::util::read_buf;
+use crate::app::{Member, MemberRole, Room, RoomShared};
+use crate::chat_command::ModAction;
+use crate::util::{mentions, read_buf};
 use once_cell::sync::Lazy;
 use regex::Regex;
-use std::collections::VecDeque;
 use std::io::{Error, ErrorKind};
-use std::sync::Arc;
+use std::time::Instant;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
-use tokio::sync::Mutex;
 use tokio::task;
+use tracing::field::Empty;
+use tracing::{debug, error, info, instrument, trace, warn, Instrument, Span};
+use zeroize::Zeroize;
 pub(crate) struct Client;
 
+pub(crate) struct JoinedRoom {
+    pub(crate) room_id: String,
+    // Only sent by newer servers
+    pub(crate) room_name: Option<String>,
+}
+
 impl Client {
+    // The spans are at error level so their fields are on every line, whatever --log-level is
+    #[instrument(
+        level = "error",
+        skip_all,
+        fields(server = remote_server, %username, room_id = Empty)
+    )]
     pub(crate) async fn create_room(
         username: String,
         room_name: String,
         password: Option<String>,
-        chat_room_record: Arc<Mutex<VecDeque<String>>>,
-        chat_room_member: Arc<Mutex<Vec<String>>>,
-        abandon_handle: Arc<Mutex<bool>>,
+        private: bool,
+        room: RoomShared,
         record_size: u32,
         remote_server: &str,
-    ) -> Result<(mpsc::Sender<String>, String), Error> {
-        let mut stream = TcpStream::connect(remote_server).await?;
+    ) -> Result<(mpsc::Sender<String>, String, Option<String>), Error> {
+        debug!("connecting");
+        let mut stream = TcpStream::connect(remote_server)
+            .await
+            .inspect_err(|e| warn!(error = %e, "failed to connect"))?;
         let (tx, rx) = mpsc::channel::<String>(10);
 
-        let mut header = format!("create\r\n{}\r\n{}", username, room_name);
-        if let Some(password_string) = password {
+        // Private rooms are hidden from room_list and can only be joined with an invite code
+        let verb = if private { "create_private" } else { "create" };
+        debug!(verb, has_password = password.is_some(), "sending handshake");
+        let mut header = format!("{}\r\n{}\r\n{}", verb, username, room_name);
+        if let Some(mut password_string) = password {
             header += "\r\n";
             header += &password_string;
+            password_string.zeroize();
         }
 
-        let _ = stream.write_all(header.as_bytes()).await?;
+        let write_result = stream.write_all(header.as_bytes()).await;
+        header.zeroize();
+        write_result.inspect_err(|e| warn!(error = %e, "failed to send handshake"))?;
 
         let (reader, _) = stream.split();
         let mut reader = BufReader::new(reader);
 
-        let buffer = Vec::from(reader.fill_buf().await?);
-        reader.consume(buffer.len());
-        let room_id = read_buf(&buffer);
-
-        Self::start_chat(
-            stream,
-            rx,
-            chat_room_record,
-            chat_room_member,
-            abandon_handle,
-            record_size,
+        let buffer = Vec::from(
+            reader
+                .fill_buf()
+                .await
+                .inspect_err(|e| warn!(error = %e, "no handshake reply"))?,
         );
+        reader.consume(buffer.len());
+        let reply = read_buf(&buffer);
+
+        if &reply == "@#$taken" {
+            warn!("username taken");
+            return Err(Error::new(ErrorKind::AlreadyExists, "Username taken"));
+        }
 
-        Ok((tx, room_id))
+        // Private rooms reply with "room_id$#$#invite_code"
+        let (room_id, invite_code) = match reply.split_once("$#$#") {
+            Some((room_id, invite_code)) => (room_id.to_string(), Some(invite_code.to_string())),
+            None => (reply, None),
+        };
+        Span::current().record("room_id", &room_id);
+        // The invite code is as good as a password, so only whether there is one gets logged
+        info!(private = invite_code.is_some(), "room created");
+
+        room.chat_room_member.lock().await.push(Member::new(
+            username.clone(),
+            MemberRole::Owner,
+            Some(Instant::now()),
+        ));
+
+        Self::start_chat(stream, rx, username, room, record_size);
+
+        Ok((tx, room_id, invite_code))
     }
 
+    #[instrument(
+        level = "error",
+        skip_all,
+        fields(server = remote_server, %username, %room_id, invite = invite_code.is_some())
+    )]
     pub(crate) async fn enter_room(
         username: String,
         room_id: String,
+        invite_code: Option<String>,
         password: Option<String>,
-        chat_room_record: Arc<Mutex<VecDeque<String>>>,
-        chat_room_member: Arc<Mutex<Vec<String>>>,
-        abandon_handle: Arc<Mutex<bool>>,
+        room: RoomShared,
         record_size: u32,
         remote_server: &str,
-    ) -> Result<(mpsc::Sender<String>, String), Error> {
-        let mut stream = TcpStream::connect(remote_server).await?;
+    ) -> Result<(mpsc::Sender<String>, JoinedRoom), Error> {
+        debug!("connecting");
+        let mut stream = TcpStream::connect(remote_server)
+            .await
+            .inspect_err(|e| warn!(error = %e, "failed to connect"))?;
         let (tx, rx) = mpsc::channel::<String>(10);
 
-        let mut header = format!("join\r\n{}\r\n{}", username, room_id);
+        let mut header = match invite_code {
+            Some(invite_code) => format!(
+                "join_invite\r\n{}\r\n{}\r\n{}",
+                username, room_id, invite_code
+            ),
+            None => format!("join\r\n{}\r\n{}", username, room_id),
+        };
 
-        if let Some(password_string) = password {
+        debug!(has_password = password.is_some(), "sending handshake");
+        if let Some(mut password_string) = password {
             header += "\r\n";
             header += &password_string;
+            password_string.zeroize();
         }
 
-        let _ = stream.write_all(header.as_bytes()).await?;
+        let write_result = stream.write_all(header.as_bytes()).await;
+        header.zeroize();
+        write_result.inspect_err(|e| warn!(error = %e, "failed to send handshake"))?;
 
         let (reader, _) = stream.split();
         let mut reader = BufReader::new(reader);
-        let buffer = Vec::from(reader.fill_buf().await?);
+        let buffer = Vec::from(
+            reader
+                .fill_buf()
+                .await
+                .inspect_err(|e| warn!(error = %e, "no handshake reply"))?,
+        );
         reader.consume(buffer.len());
 
         let x = String::from_utf8_lossy(&buffer).to_string();
-
-        if &x == "@#$failed" {
-            return Err(Error::new(ErrorKind::BrokenPipe, "Room not found"));
-        } else if &x == "@#$wrong" {
-            return Err(Error::new(ErrorKind::InvalidInput, "Password not matched"));
+        trace!(reply = ?x, "handshake reply");
+
+        let rejection = match x.as_str() {
+            "@#$failed" => Some(Error::new(ErrorKind::BrokenPipe, "Room not found")),
+            "@#$wrong" => Some(Error::new(ErrorKind::InvalidInput, "Password not matched")),
+            "@#$taken" => Some(Error::new(ErrorKind::AlreadyExists, "Username taken")),
+            "@#$password" => Some(Error::new(ErrorKind::PermissionDenied, "Password required")),
+            _ => None,
+        };
+        if let Some(e) = rejection {
+            warn!(error = %e, "join rejected");
+            return Err(e);
         }
 
+        // The first line is either a bare room ID or "room_id$#$#room_name$#$#topic"
         let room_id_and_people = x.split("\r\n").collect::<Vec<&str>>();
-        let room_id = room_id_and_people[0].to_string();
+        let room_info = room_id_and_people[0].split("$#$#").collect::<Vec<&str>>();
+        let joined_room = JoinedRoom {
+            room_id: room_info[0].to_string(),
+            room_name: room_info.get(1).map(|x| x.to_string()),
+        };
+        if let Some(topic) = room_info.get(2) {
+            *room.room_topic.lock().await = topic.to_string();
+        }
 
-        let mut chat_room_member_handle = chat_room_member.lock().await;
+        // Each member entry is either a bare username or "username$#$#role"
+        let mut chat_room_member_handle = room.chat_room_member.lock().await;
         for i in 1..room_id_and_people.len() {
-            chat_room_member_handle.push(room_id_and_people[i].to_string());
+            let tmp = room_id_and_people[i].split("$#$#").collect::<Vec<&str>>();
+            let role = match tmp.get(1).map(|x| x.trim()) {
+                Some("owner") => MemberRole::Owner,
+                _ => MemberRole::Member,
+            };
+            let joined_at = if tmp[0] == username {
+                Some(Instant::now())
+            } else {
+                None
+            };
+            chat_room_member_handle.push(Member::new(tmp[0].to_string(), role, joined_at));
         }
+        info!(members = chat_room_member_handle.len(), "joined room");
         drop(chat_room_member_handle);
 
-        Self::start_chat(
-            stream,
-            rx,
-            chat_room_record,
-            chat_room_member,
-            abandon_handle,
-            record_size,
-        );
+        Self::start_chat(stream, rx, username, room, record_size);
 
-        Ok((tx, room_id))
+        Ok((tx, joined_room))
     }
 
     fn start_chat(
         mut stream: TcpStream,
         mut rx: mpsc::Receiver<String>,
-        chat_room_record: Arc<Mutex<VecDeque<String>>>,
-        chat_room_member: Arc<Mutex<Vec<String>>>,
-        abandon_handle: Arc<Mutex<bool>>,
+        username: String,
+        room: RoomShared,
         record_size: u32,
     ) {
-        task::spawn(async move {
+        // The task logs under the span of the create_room or enter_room call that started it
+        let span = Span::current();
+        let watched = room.clone();
+        let chat = task::spawn(async move {
             let (reader, mut writer) = stream.split();
             let record_size = record_size as usize;
             let mut reader = BufReader::new(reader);
+            let RoomShared {
+                chat_room_member,
+                abandon: abandon_handle,
+                kick_reason,
+                mention_count,
+                room_topic,
+                highlight_rules,
+                ignored_users,
+                ..
+            } = &room;
 
             loop {
                 tokio::select! {
@@ -128,38 +223,107 @@ impl Client {
                             let buffer = Vec::from(result.unwrap());
                             reader.consume(buffer.len());
                             if buffer.len() == 0 {
+                                info!("server closed the connection");
                                 break;
                             }
 
-                            let msg = String::from_utf8_lossy(&buffer);
+                            let mut msg = String::from_utf8_lossy(&buffer).to_string();
+                            trace!(frame = ?msg, "frame in");
+
+                            if let Some((sender, text)) = parse_private_msg(&msg) {
+                                let mut chat_room_member_handle = chat_room_member.lock().await;
+                                if let Some(member) = chat_room_member_handle.iter_mut().find(|x| x.name == sender) {
+                                    member.last_active = Some(Instant::now());
+                                }
+                                drop(chat_room_member_handle);
+                                msg = format!("[DM from {}] {}", sender, text);
+                            } else if let Some((action, actor, target)) = parse_moderation(&msg) {
+                                let mut chat_room_member_handle = chat_room_member.lock().await;
+                                match action {
+                                    ModAction::Kick | ModAction::Ban => {
+                                        chat_room_member_handle.retain(|x| x.name != target);
+                                    }
+                                    ModAction::Mute | ModAction::Unmute => {
+                                        if let Some(member) = chat_room_member_handle.iter_mut().find(|x| x.name == target) {
+                                            member.muted = action == ModAction::Mute;
+                                        }
+                                    }
+                                    ModAction::Transfer => {
+                                        for member in chat_room_member_handle.iter_mut() {
+                                            if member.name == target {
+                                                member.role = MemberRole::Owner;
+                                            } else if member.name == actor {
+                                                member.role = MemberRole::Member;
+                                            }
+                                        }
+                                    }
+                                }
+                                drop(chat_room_member_handle);
 
-                            if let Some(disconnected_user) = match_regex_left(&msg) {
+                                msg = describe_moderation(action, &actor, &target, &username);
+                                if matches!(action, ModAction::Kick | ModAction::Ban) && target == username {
+                                    info!(action = action.as_str(), by = %actor, "removed from the room");
+                                    *kick_reason.lock().await = Some(msg);
+                                    break;
+                                }
+                            } else if let Some((actor, has_password)) = parse_password_change(&msg) {
+                                msg = if has_password {
+                                    format!("{} set a new room password", actor)
+                                } else {
+                                    format!("{} removed the room password", actor)
+                                };
+                            } else if let Some((setter, topic)) = parse_topic_change(&msg) {
+                                msg = if topic.is_empty() {
+                                    format!("{} cleared the room topic", setter)
+                                } else {
+                                    format!("{} set the room topic to: {}", setter, topic)
+                                };
+                                *room_topic.lock().await = topic;
+                            } else if let Some(disconnected_user) = match_regex_left(&msg) {
                                 let mut chat_room_member_handle = chat_room_member.lock().await;
-                                if let Some(pos) = chat_room_member_handle.iter().position(|x| *x == disconnected_user) {
+                                if let Some(pos) = chat_room_member_handle.iter().position(|x| x.name == disconnected_user) {
                                     chat_room_member_handle.remove(pos);
                                 }
                             } else if let Some(joined_user) = match_regex_join(&msg) {
                                 let mut chat_room_member_handle = chat_room_member.lock().await;
-                                chat_room_member_handle.push(joined_user);
+                                if !chat_room_member_handle.iter().any(|x| x.name == joined_user) {
+                                    chat_room_member_handle.push(Member::new(joined_user, MemberRole::Member, Some(Instant::now())));
+                                }
+                            } else if let Some(sender) = match_regex_sender(&msg) {
+                                let mut chat_room_member_handle = chat_room_member.lock().await;
+                                if let Some(member) = chat_room_member_handle.iter_mut().find(|x| x.name == sender) {
+                                    member.last_active = Some(Instant::now());
+                                }
+                                drop(chat_room_member_handle);
+                                let text = &msg[sender.len() + 1..];
+                                let ignored = ignored_users.lock().await.contains(&sender);
+                                if sender != username && !ignored && (mentions(text, &username) || highlight_rules.iter().any(|re| re.is_match(text))) {
+                                    *mention_count.lock().await += 1;
+                                }
                             }
 
-                            let mut room_record_handle = chat_room_record.lock().await;
-                            room_record_handle.push_back(msg.trim_end().to_string());
-                            if room_record_handle.len() > record_size {
-                                room_record_handle.pop_front();
-                            }
-                            drop(room_record_handle);
+                            room.push_record(msg.trim_end().to_string(), record_size).await;
 
-                        } else {
+                        } else if let Err(e) = result {
                             // Stream timeout or reset
+                            warn!(error = %e, "connection lost");
                             break;
                         }
 
                     }
                     result = rx.recv() => {
                         if let Some(user_input) = result {
-                            let _ = writer.write_all(user_input.as_bytes()).await;
+                            // Password frames carry the new password itself
+                            if user_input.starts_with("@#$password") {
+                                trace!("frame out: password change");
+                            } else {
+                                trace!(frame = ?user_input, "frame out");
+                            }
+                            if let Err(e) = writer.write_all(user_input.as_bytes()).await {
+                                warn!(error = %e, "failed to send frame");
+                            }
                         } else {
+                            debug!("left the room");
                             break;
                         }
                     }
@@ -169,37 +333,109 @@ impl Client {
             let mut abandon = abandon_handle.lock().await;
             *abandon = true;
             // dbg!("client closed!");
-        });
+        }
+        .instrument(span.clone()));
+
+        // The panic hook leaves worker threads alone, so a crashed chat task has to send the
+        // UI back to the main menu itself instead of leaving it in a dead room
+        task::spawn(
+            async move {
+                if let Err(e) = chat.await {
+                    if e.is_panic() {
+                        error!("chat task panicked");
+                        *watched.kick_reason.lock().await = Some(
+                            "Left the room after an internal error, see the log for details"
+                                .to_string(),
+                        );
+                        *watched.abandon.lock().await = true;
+                    }
+                }
+            }
+            .instrument(span),
+        );
     }
 
-    pub(crate) async fn get_room_list(
-        remote_server: &str,
-    ) -> Result<Vec<(String, String, bool)>, Error> {
-        let mut stream = TcpStream::connect(remote_server).await?;
+    pub(crate) fn private_msg_frame(target: &str, text: &str) -> String {
+        format!("@#$dm\r\n{}\r\n{}", target, text)
+    }
+
+    pub(crate) fn topic_frame(topic: &str) -> String {
+        format!("@#$topic\r\n{}", topic)
+    }
+
+    pub(crate) fn password_frame(password: Option<&str>) -> String {
+        match password {
+            Some(password) => format!("@#$password\r\nset\r\n{}", password),
+            None => "@#$password\r\nclear".to_string(),
+        }
+    }
+
+    pub(crate) fn moderation_frame(action: ModAction, target: &str) -> String {
+        format!("@#$mod\r\n{}\r\n{}", action.as_str(), target)
+    }
+
+    #[instrument(level = "error", skip_all, fields(server = remote_server))]
+    pub(crate) async fn get_room_list(remote_server: &str) -> Result<Vec<Room>, Error> {
+        debug!("connecting");
+        let mut stream = TcpStream::connect(remote_server)
+            .await
+            .inspect_err(|e| warn!(error = %e, "failed to connect"))?;
         let (reader, mut writer) = stream.split();
         let mut reader = BufReader::new(reader);
-        writer.write_all("room_list".as_bytes()).await?;
-
-        let buffer = Vec::from(reader.fill_buf().await?);
+        writer
+            .write_all("room_list".as_bytes())
+            .await
+            .inspect_err(|e| warn!(error = %e, "failed to request the room list"))?;
+
+        let buffer = Vec::from(
+            reader
+                .fill_buf()
+                .await
+                .inspect_err(|e| warn!(error = %e, "no room list reply"))?,
+        );
         reader.consume(buffer.len());
 
         let data = String::from_utf8_lossy(&buffer).to_string();
-        let mut room_list = vec![];
-
-        for s in data.split("\r\n") {
-            let tmp = s.split("$#$#").collect::<Vec<&str>>();
+        trace!(frame = ?data, "room list reply");
+        let room_list = parse_room_list(&data);
+        debug!(rooms = room_list.len(), "fetched room list");
+        Ok(room_list)
+    }
+}
 
-            if tmp.len() >= 3 {
-                let has_password = match tmp[2].trim() {
-                    "1" => true,
-                    _ => false,
-                };
-                room_list.push((tmp[0].to_string(), tmp[1].to_string(), has_password));
-            }
+// One room per line. Older servers only send the first three fields, empty or unparsable
+// optional fields are left out.
+fn parse_room_list(data: &str) -> Vec<Room> {
+    let mut room_list = vec![];
+
+    for s in data.split("\r\n") {
+        let tmp = s.split("$#$#").collect::<Vec<&str>>();
+
+        // id, name, has_password[, member_count, creator, created_at, topic]
+        if tmp.len() >= 3 {
+            let has_password = match tmp[2].trim() {
+                "1" => true,
+                _ => false,
+            };
+            let optional_field = |idx: usize| {
+                tmp.get(idx)
+                    .map(|x| x.trim())
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_string())
+            };
+            room_list.push(Room {
+                id: tmp[0].to_string(),
+                name: tmp[1].to_string(),
+                has_password,
+                member_count: optional_field(3).and_then(|x| x.parse().ok()),
+                creator: optional_field(4),
+                created_at: optional_field(5).and_then(|x| x.parse().ok()),
+                topic: optional_field(6),
+            });
         }
-
-        Ok(room_list)
     }
+
+    room_list
 }
 
 fn match_regex_left(hay: &str) -> Option<String> {
@@ -229,3 +465,138 @@ fn match_regex_join(hay: &str) -> Option<String> {
     }
     None
 }
+
+fn match_regex_sender(hay: &str) -> Option<String> {
+    static RE3: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"^([^!@#$%\^\&\*\(\)\+=\[\]\{\}:;'"/<>|\\`~\?,\.\s]+):"#).unwrap()
+    });
+    if let Some(cap) = RE3.captures(hay) {
+        if let Some(first) = cap.get(1) {
+            return Some(first.as_str().to_string());
+        }
+    }
+    None
+}
+
+fn parse_private_msg(msg: &str) -> Option<(String, String)> {
+    let body = msg.strip_prefix("@#$dm\r\n")?;
+    let (sender, text) = body.split_once("\r\n")?;
+    Some((sender.to_string(), text.trim_end().to_string()))
+}
+
+fn parse_password_change(msg: &str) -> Option<(String, bool)> {
+    let body = msg.strip_prefix("@#$password_changed\r\n")?;
+    let (actor, flag) = body.trim_end().split_once("\r\n")?;
+    Some((actor.to_string(), flag == "1"))
+}
+
+fn parse_topic_change(msg: &str) -> Option<(String, String)> {
+    let body = msg.strip_prefix("@#$topic\r\n")?;
+    let (setter, topic) = body.split_once("\r\n").unwrap_or((body, ""));
+    Some((setter.trim_end().to_string(), topic.trim_end().to_string()))
+}
+
+// Moderation events are broadcast as "@#$mod\r\naction\r\nactor\r\ntarget"
+fn parse_moderation(msg: &str) -> Option<(ModAction, String, String)> {
+    let body = msg.strip_prefix("@#$mod\r\n")?;
+    let parts = body.trim_end().split("\r\n").collect::<Vec<&str>>();
+    if parts.len() < 3 {
+        return None;
+    }
+    let action = parts[0].parse::<ModAction>().ok()?;
+    Some((action, parts[1].to_string(), parts[2].to_string()))
+}
+
+fn describe_moderation(action: ModAction, actor: &str, target: &str, username: &str) -> String {
+    match action {
+        ModAction::Kick if target == username => {
+            format!("You were kicked from the room by {}", actor)
+        }
+        ModAction::Ban if target == username => {
+            format!("You were banned from the room by {}", actor)
+        }
+        ModAction::Kick => format!("{} kicked {} from the room", actor, target),
+        ModAction::Ban => format!("{} banned {} from the room", actor, target),
+        ModAction::Mute => format!("{} muted {}", actor, target),
+        ModAction::Unmute => format!("{} unmuted {}", actor, target),
+        ModAction::Transfer => format!("{} transferred room ownership to {}", actor, target),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_room_list_from_an_older_server() {
+        let rooms = parse_room_list("a1$#$#Lobby$#$#0\r\nb2$#$#Secret$#$#1\r\n");
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(
+            (rooms[0].id.as_str(), rooms[0].name.as_str()),
+            ("a1", "Lobby")
+        );
+        assert!(!rooms[0].has_password);
+        assert!(rooms[1].has_password);
+        assert!(rooms[1].member_count.is_none());
+        assert!(rooms[1].creator.is_none());
+        assert!(rooms[1].created_at.is_none());
+        assert!(rooms[1].topic.is_none());
+    }
+
+    #[test]
+    fn parse_room_list_with_every_field() {
+        let rooms = parse_room_list("a1$#$#Lobby$#$#0$#$#3$#$#alice$#$#1700000000$#$#say hi\r\n");
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].member_count, Some(3));
+        assert_eq!(rooms[0].creator.as_deref(), Some("alice"));
+        assert_eq!(rooms[0].created_at, Some(1700000000));
+        assert_eq!(rooms[0].topic.as_deref(), Some("say hi"));
+    }
+
+    #[test]
+    fn parse_room_list_skips_bad_optional_fields() {
+        let rooms = parse_room_list("a1$#$#Lobby$#$#0$#$#many$#$# $#$#-5$#$#\r\nbroken$#$#line");
+        assert_eq!(rooms.len(), 1);
+        assert!(rooms[0].member_count.is_none());
+        assert!(rooms[0].creator.is_none());
+        assert!(rooms[0].created_at.is_none());
+        assert!(rooms[0].topic.is_none());
+    }
+
+    #[test]
+    fn parses_private_messages() {
+        assert_eq!(
+            parse_private_msg("@#$dm\r\nbob\r\nhi there\r\n"),
+            Some(("bob".to_string(), "hi there".to_string()))
+        );
+        assert_eq!(parse_private_msg("@#$dm\r\nbob"), None);
+        assert_eq!(parse_private_msg("bob: @#$dm\r\nbob\r\nhi"), None);
+    }
+
+    #[test]
+    fn parses_topic_changes() {
+        assert_eq!(
+            parse_topic_change("@#$topic\r\nalice\r\nRust only\r\n"),
+            Some(("alice".to_string(), "Rust only".to_string()))
+        );
+        assert_eq!(
+            parse_topic_change("@#$topic\r\nalice\r\n"),
+            Some(("alice".to_string(), String::new()))
+        );
+        assert_eq!(
+            parse_topic_change("@#$topic\r\nalice"),
+            Some(("alice".to_string(), String::new()))
+        );
+        assert_eq!(parse_topic_change("alice: @#$topic\r\nx"), None);
+    }
+
+    #[test]
+    fn parses_moderation_events() {
+        assert!(matches!(
+            parse_moderation("@#$mod\r\nban\r\nalice\r\nbob\r\n"),
+            Some((ModAction::Ban, actor, target)) if actor == "alice" && target == "bob"
+        ));
+        assert!(parse_moderation("@#$mod\r\nban\r\nalice").is_none());
+        assert!(parse_moderation("@#$mod\r\nslap\r\nalice\r\nbob").is_none());
+    }
+}
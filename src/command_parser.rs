@@ -1,24 +1,26 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    #[clap(subcommand)]
-    pub operation: Operation,
+    /// Path to the config file, instead of the one in your config directory
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Name of the server to connect to, as listed in the config file
+    #[arg(long)]
+    pub server: Option<String>,
+    /// Write a log at this level to chat_client/logs in your data directory, rotated daily
+    #[arg(long, value_enum)]
+    pub log_level: Option<LogLevel>,
 }
 
-#[derive(Subcommand, Debug)]
-pub enum Operation {
-    /// Create a room
-    Create {
-        /// Your username
-        username: String,
-    },
-    /// Join a room with room ID
-    Join {
-        /// Your username
-        username: String,
-        /// The ID of the room you would like to join
-        room_id: String,
-    },
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    /// Also logs every frame sent and received
+    Trace,
 }
@@ -0,0 +1,295 @@
+use crate::keymap::Keymap;
+use crate::theme::Theme;
+use crate::util::is_valid_string;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, DocumentMut};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // The first server is used unless another one is picked with --server
+    pub servers: Vec<ServerConfig>,
+    // Prefilled on the Create and Join screens
+    pub username: Option<String>,
+    // Number of chat messages kept per room
+    pub history_size: u32,
+    pub theme: ThemeConfig,
+    pub keybindings: KeybindingsConfig,
+    pub notifications: NotificationConfig,
+    // Ignored usernames, keyed by server address
+    pub ignore: HashMap<String, Vec<String>>,
+    pub highlight: HighlightConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            servers: vec![],
+            username: None,
+            history_size: 100,
+            theme: ThemeConfig::default(),
+            keybindings: KeybindingsConfig::default(),
+            notifications: NotificationConfig::default(),
+            ignore: HashMap::new(),
+            highlight: HighlightConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    pub name: String,
+    pub address: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    // One of theme::PRESET_NAMES, "dark" when unset
+    pub preset: Option<String>,
+    // Named styles from theme::Theme overriding the preset, like `own_msg = "yellow"`
+    #[serde(flatten)]
+    pub styles: BTreeMap<String, StyleConfig>,
+}
+
+// Either just a foreground color or a full style. Colors are names ("lightgreen") or hex ("#87d787").
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StyleConfig {
+    Color(String),
+    Full {
+        fg: Option<String>,
+        bg: Option<String>,
+        bold: Option<bool>,
+        italic: Option<bool>,
+        underlined: Option<bool>,
+        reversed: Option<bool>,
+    },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeybindingsConfig {
+    // One of keymap::PRESET_NAMES, "default" when unset
+    pub preset: Option<String>,
+    // Action names from keymap::Action mapped to the keys that trigger them, like `enter_input = "a"`
+    #[serde(flatten)]
+    pub bindings: BTreeMap<String, KeySpec>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeySpec {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationConfig {
+    // Ring the terminal bell on mentions and highlights
+    pub bell: bool,
+    // Ask the terminal for a desktop notification
+    pub desktop: bool,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        NotificationConfig {
+            bell: true,
+            desktop: true,
+        }
+    }
+}
+
+// Messages matching any of these are colored and notified like mentions
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HighlightConfig {
+    // Matched case-insensitively as whole words
+    pub words: Vec<String>,
+    // Regular expressions, matched as written
+    pub patterns: Vec<String>,
+}
+
+impl HighlightConfig {
+    pub fn compile(&self) -> Result<Vec<Regex>, regex::Error> {
+        self.words
+            .iter()
+            .map(|word| format!(r"(?i)\b{}\b", regex::escape(word)))
+            .chain(self.patterns.iter().cloned())
+            .map(|pattern| Regex::new(&pattern))
+            .collect()
+    }
+}
+
+impl Config {
+    // $XDG_CONFIG_HOME or ~/.config, also on macOS where dirs::config_dir() would point to
+    // ~/Library/Application Support. Windows keeps its own config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = if cfg!(windows) {
+            dirs::config_dir()
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .or_else(|| dirs::home_dir().map(|home| home.join(".config")))
+        };
+        dir.map(|dir| dir.join("chat_client").join("config.toml"))
+    }
+
+    // A missing file is not an error, it just means nothing has been configured yet
+    pub fn load(path: &Path) -> Result<Config, Error> {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                let config: Config = toml::from_str(&content).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{}: {}", path.display(), e.message()),
+                    )
+                })?;
+                let problems = config.validate();
+                if !problems.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{}:\n  {}", path.display(), problems.join("\n  ")),
+                    ));
+                }
+                Ok(config)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Only the [ignore] table is rewritten, the file is the user's and keeps its comments and layout
+    pub fn save_ignore(&self, path: &Path) -> Result<(), Error> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut document = content
+            .parse::<DocumentMut>()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        if self.ignore.is_empty() {
+            document.remove("ignore");
+        } else {
+            let ignore = document
+                .entry("ignore")
+                .or_insert(toml_edit::table())
+                .as_table_like_mut()
+                .ok_or(Error::new(ErrorKind::InvalidData, "ignore is not a table"))?;
+            let removed = ignore
+                .iter()
+                .map(|(server, _)| server.to_string())
+                .filter(|server| !self.ignore.contains_key(server))
+                .collect::<Vec<String>>();
+            for server in removed {
+                ignore.remove(&server);
+            }
+            let mut servers = self.ignore.keys().collect::<Vec<&String>>();
+            servers.sort();
+            for server in servers {
+                let names = self.ignore[server].iter().collect::<Array>();
+                ignore.insert(server, toml_edit::value(names));
+            }
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, document.to_string())
+    }
+
+    pub fn server(&self, name: &str) -> Option<&ServerConfig> {
+        self.servers.iter().find(|server| server.name == name)
+    }
+
+    // Collects every problem so they can all be fixed in one go
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        for (idx, server) in self.servers.iter().enumerate() {
+            if server.name.is_empty() {
+                problems.push(format!("servers[{}]: name must not be empty", idx));
+            } else if self.servers[..idx].iter().any(|x| x.name == server.name) {
+                problems.push(format!("servers[{}]: duplicate name {}", idx, server.name));
+            }
+            if server
+                .address
+                .rsplit_once(':')
+                .is_none_or(|(host, port)| host.is_empty() || port.parse::<u16>().is_err())
+            {
+                problems.push(format!(
+                    "servers[{}]: address should look like host:port, got {}",
+                    idx, server.address
+                ));
+            }
+        }
+
+        if let Some(username) = &self.username {
+            if username.is_empty() || username.len() > 50 || !is_valid_string(username) {
+                problems.push(format!("username: {} is not a valid username", username));
+            }
+        }
+
+        if !(1..=10000).contains(&self.history_size) {
+            problems.push(format!(
+                "history_size: should be between 1 and 10000, got {}",
+                self.history_size
+            ));
+        }
+
+        if let Err(e) = Theme::from_config(&self.theme, false) {
+            problems.push(e);
+        }
+
+        if let Err(e) = Keymap::from_config(&self.keybindings) {
+            problems.push(e);
+        }
+
+        if let Err(e) = self.highlight.compile() {
+            problems.push(format!("highlight: invalid pattern: {}", e));
+        }
+
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_ignore_keeps_the_rest_of_the_file() {
+        let path =
+            std::env::temp_dir().join(format!("chat_client_test_{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "# my servers\n[[servers]]\nname = \"home\"\naddress = \"localhost:8080\"\n\n[ignore]\n\"old:1\" = [\"bob\"]\n",
+        )
+        .unwrap();
+
+        let mut config = Config::load(&path).unwrap();
+        config.ignore.remove("old:1");
+        config
+            .ignore
+            .insert("localhost:8080".to_string(), vec!["eve".to_string()]);
+        config.save_ignore(&path).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(content.starts_with("# my servers\n[[servers]]\nname = \"home\"\n"));
+        assert!(!content.contains("old:1"));
+        assert!(!content.contains("history_size"));
+        let saved: Config = toml::from_str(&content).unwrap();
+        assert_eq!(saved.ignore["localhost:8080"], vec!["eve"]);
+    }
+}
@@ -0,0 +1,651 @@
+use crate::config::{KeySpec, KeybindingsConfig};
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::fmt;
+
+pub const PRESET_NAMES: &[&str] = &["default", "vim", "emacs"];
+
+// Where a key was pressed. The same key can mean different things in different contexts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyContext {
+    Entry,
+    // "Set a password?" prompt on the Create screen
+    CreatePrompt,
+    // Screens where typed characters go into a text field
+    Form,
+    // Password fields, where the toggle shows or hides what was typed
+    PasswordForm,
+    RoomList,
+    RecentRooms,
+    ChatNormal,
+    MemberList,
+    ChatInput,
+    ExitConfirm,
+    // The help overlay, on top of whichever screen opened it
+    HelpOverlay,
+}
+
+impl KeyContext {
+    // Plain characters are text there, so only named keys and Ctrl/Alt combinations can be bound
+    fn takes_text(&self) -> bool {
+        matches!(
+            self,
+            KeyContext::Form | KeyContext::PasswordForm | KeyContext::ChatInput
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyContext::Entry => "main menu",
+            KeyContext::CreatePrompt => "password prompt",
+            KeyContext::Form => "forms",
+            KeyContext::PasswordForm => "password fields",
+            KeyContext::RoomList => "room list",
+            KeyContext::RecentRooms => "recent rooms",
+            KeyContext::ChatNormal => "chat room",
+            KeyContext::MemberList => "member list",
+            KeyContext::ChatInput => "chat input",
+            KeyContext::ExitConfirm => "leave room prompt",
+            KeyContext::HelpOverlay => "help",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Up,
+    Down,
+    Select,
+    Back,
+    Submit,
+    Confirm,
+    Quit,
+    NextOption,
+    NextField,
+    TogglePassword,
+    SetPassword,
+    NoPassword,
+    TogglePrivate,
+    ReloadRooms,
+    Filter,
+    OpenOnly,
+    PasswordOnly,
+    CycleSort,
+    Forget,
+    EnterInput,
+    LeaveRoom,
+    FocusMembers,
+    PrivateMessage,
+    Mention,
+    ToggleIgnore,
+    Kick,
+    Complete,
+    Help,
+}
+
+const ACTIONS: &[Action] = &[
+    Action::Up,
+    Action::Down,
+    Action::Select,
+    Action::Back,
+    Action::Submit,
+    Action::Confirm,
+    Action::Quit,
+    Action::NextOption,
+    Action::NextField,
+    Action::TogglePassword,
+    Action::SetPassword,
+    Action::NoPassword,
+    Action::TogglePrivate,
+    Action::ReloadRooms,
+    Action::Filter,
+    Action::OpenOnly,
+    Action::PasswordOnly,
+    Action::CycleSort,
+    Action::Forget,
+    Action::EnterInput,
+    Action::LeaveRoom,
+    Action::FocusMembers,
+    Action::PrivateMessage,
+    Action::Mention,
+    Action::ToggleIgnore,
+    Action::Kick,
+    Action::Complete,
+    Action::Help,
+];
+
+impl Action {
+    // Name used in the [keybindings] section of the config
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Select => "select",
+            Action::Back => "back",
+            Action::Submit => "submit",
+            Action::Confirm => "confirm",
+            Action::Quit => "quit",
+            Action::NextOption => "next_option",
+            Action::NextField => "next_field",
+            Action::TogglePassword => "toggle_password",
+            Action::SetPassword => "set_password",
+            Action::NoPassword => "no_password",
+            Action::TogglePrivate => "toggle_private",
+            Action::ReloadRooms => "reload_rooms",
+            Action::Filter => "filter",
+            Action::OpenOnly => "open_only",
+            Action::PasswordOnly => "password_only",
+            Action::CycleSort => "cycle_sort",
+            Action::Forget => "forget",
+            Action::EnterInput => "enter_input",
+            Action::LeaveRoom => "leave_room",
+            Action::FocusMembers => "focus_members",
+            Action::PrivateMessage => "private_message",
+            Action::Mention => "mention",
+            Action::ToggleIgnore => "toggle_ignore",
+            Action::Kick => "kick",
+            Action::Complete => "complete",
+            Action::Help => "help",
+        }
+    }
+
+    // What the action does, for the help overlay
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Up => "move up",
+            Action::Down => "move down",
+            Action::Select => "open the selection",
+            Action::Back => "go back",
+            Action::Submit => "submit",
+            Action::Confirm => "confirm",
+            Action::Quit => "quit",
+            Action::NextOption => "switch selection",
+            Action::NextField => "next field",
+            Action::TogglePassword => "show or hide the password",
+            Action::SetPassword => "set a room password",
+            Action::NoPassword => "create without a password",
+            Action::TogglePrivate => "toggle a private room",
+            Action::ReloadRooms => "reload the room list",
+            Action::Filter => "filter rooms by name",
+            Action::OpenOnly => "only show open rooms",
+            Action::PasswordOnly => "only show password protected rooms",
+            Action::CycleSort => "change the sort order",
+            Action::Forget => "forget the selected room",
+            Action::EnterInput => "start typing a message",
+            Action::LeaveRoom => "leave the room",
+            Action::FocusMembers => "focus the member list",
+            Action::PrivateMessage => "private message the member",
+            Action::Mention => "mention the member",
+            Action::ToggleIgnore => "ignore or unignore the member",
+            Action::Kick => "kick the member, owner only",
+            Action::Complete => "complete usernames and commands",
+            Action::Help => "show or close this help",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        ACTIONS.iter().find(|action| action.as_str() == s).copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyBinding {
+    code: KeyCode,
+    // Only Ctrl and Alt, Shift is already part of the character
+    modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    fn new(code: KeyCode) -> Self {
+        KeyBinding {
+            code,
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+
+    fn ctrl(c: char) -> Self {
+        KeyBinding {
+            code: KeyCode::Char(c),
+            modifiers: KeyModifiers::CONTROL,
+        }
+    }
+
+    fn alt(c: char) -> Self {
+        KeyBinding {
+            code: KeyCode::Char(c),
+            modifiers: KeyModifiers::ALT,
+        }
+    }
+
+    // Accepts "i", "Tab", "Esc", "Up", "F1", "Ctrl-n" / "C-n", "Alt-x" / "M-x" and so on
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = s;
+        loop {
+            if let Some(stripped) = rest.strip_prefix("Ctrl-").or(rest.strip_prefix("C-")) {
+                modifiers |= KeyModifiers::CONTROL;
+                rest = stripped;
+            } else if let Some(stripped) = rest.strip_prefix("Alt-").or(rest.strip_prefix("M-")) {
+                modifiers |= KeyModifiers::ALT;
+                rest = stripped;
+            } else {
+                break;
+            }
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest {
+                "Tab" => KeyCode::Tab,
+                "BackTab" => KeyCode::BackTab,
+                "Esc" => KeyCode::Esc,
+                "Enter" => KeyCode::Enter,
+                "Space" => KeyCode::Char(' '),
+                "Backspace" => KeyCode::Backspace,
+                "Delete" => KeyCode::Delete,
+                "Up" => KeyCode::Up,
+                "Down" => KeyCode::Down,
+                "Left" => KeyCode::Left,
+                "Right" => KeyCode::Right,
+                "Home" => KeyCode::Home,
+                "End" => KeyCode::End,
+                "PageUp" => KeyCode::PageUp,
+                "PageDown" => KeyCode::PageDown,
+                _ => KeyCode::F(
+                    rest.strip_prefix('F')?
+                        .parse()
+                        .ok()
+                        .filter(|n| (1..=12).contains(n))?,
+                ),
+            },
+        };
+        Some(KeyBinding { code, modifiers })
+    }
+
+    fn matches(&self, key: &KeyEvent) -> bool {
+        self.code == key.code
+            && self.modifiers == key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT)
+    }
+
+    fn is_plain_char(&self) -> bool {
+        matches!(self.code, KeyCode::Char(_)) && self.modifiers.is_empty()
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) if self.modifiers.is_empty() => write!(f, "'{}'", c),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            KeyCode::BackTab => write!(f, "BackTab"),
+            KeyCode::PageUp => write!(f, "PageUp"),
+            KeyCode::PageDown => write!(f, "PageDown"),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(KeyContext, Action, Vec<KeyBinding>)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        use Action::*;
+        use KeyContext::*;
+
+        let key = KeyBinding::new;
+        let chr = |c| KeyBinding::new(KeyCode::Char(c));
+        Keymap {
+            bindings: vec![
+                (Entry, NextOption, vec![key(KeyCode::Tab)]),
+                (Entry, Select, vec![key(KeyCode::Enter)]),
+                (Entry, Quit, vec![chr('q')]),
+                (CreatePrompt, SetPassword, vec![chr('y')]),
+                (CreatePrompt, NoPassword, vec![chr('n')]),
+                (CreatePrompt, TogglePrivate, vec![chr('p')]),
+                (CreatePrompt, Back, vec![key(KeyCode::Esc)]),
+                (Form, NextField, vec![key(KeyCode::Tab)]),
+                (Form, Submit, vec![key(KeyCode::Enter)]),
+                (Form, Back, vec![key(KeyCode::Esc)]),
+                (PasswordForm, TogglePassword, vec![key(KeyCode::Tab)]),
+                (PasswordForm, Submit, vec![key(KeyCode::Enter)]),
+                (PasswordForm, Back, vec![key(KeyCode::Esc)]),
+                (RoomList, Up, vec![key(KeyCode::Up)]),
+                (RoomList, Down, vec![key(KeyCode::Down)]),
+                (RoomList, Select, vec![key(KeyCode::Enter)]),
+                (RoomList, ReloadRooms, vec![chr('r')]),
+                (RoomList, Filter, vec![chr('/')]),
+                (RoomList, OpenOnly, vec![chr('o')]),
+                (RoomList, PasswordOnly, vec![chr('p')]),
+                (RoomList, CycleSort, vec![chr('s')]),
+                (RoomList, Back, vec![key(KeyCode::Esc)]),
+                (RecentRooms, Up, vec![key(KeyCode::Up)]),
+                (RecentRooms, Down, vec![key(KeyCode::Down)]),
+                (RecentRooms, Select, vec![key(KeyCode::Enter)]),
+                (RecentRooms, Forget, vec![chr('d')]),
+                (RecentRooms, Back, vec![key(KeyCode::Esc)]),
+                (ChatNormal, EnterInput, vec![chr('i')]),
+                (ChatNormal, LeaveRoom, vec![chr('q')]),
+                (ChatNormal, FocusMembers, vec![key(KeyCode::Tab)]),
+                (MemberList, Up, vec![key(KeyCode::Up)]),
+                (MemberList, Down, vec![key(KeyCode::Down)]),
+                (MemberList, PrivateMessage, vec![chr('d')]),
+                (MemberList, Mention, vec![chr('m')]),
+                (MemberList, ToggleIgnore, vec![chr('x')]),
+                (MemberList, Kick, vec![chr('k')]),
+                (MemberList, Back, vec![key(KeyCode::Tab), key(KeyCode::Esc)]),
+                (ChatInput, Complete, vec![key(KeyCode::Tab)]),
+                (ChatInput, Submit, vec![key(KeyCode::Enter)]),
+                (ChatInput, Back, vec![key(KeyCode::Esc)]),
+                (ExitConfirm, Confirm, vec![chr('y')]),
+                (ExitConfirm, Back, vec![chr('n')]),
+                (HelpOverlay, Up, vec![key(KeyCode::Up)]),
+                (HelpOverlay, Down, vec![key(KeyCode::Down)]),
+                (HelpOverlay, Back, vec![key(KeyCode::Esc), chr('q')]),
+                // F1 everywhere, since '?' is just text while typing
+                (Entry, Help, vec![chr('?'), key(KeyCode::F(1))]),
+                (CreatePrompt, Help, vec![chr('?'), key(KeyCode::F(1))]),
+                (Form, Help, vec![key(KeyCode::F(1))]),
+                (PasswordForm, Help, vec![key(KeyCode::F(1))]),
+                (RoomList, Help, vec![chr('?'), key(KeyCode::F(1))]),
+                (RecentRooms, Help, vec![chr('?'), key(KeyCode::F(1))]),
+                (ChatNormal, Help, vec![chr('?'), key(KeyCode::F(1))]),
+                (MemberList, Help, vec![chr('?'), key(KeyCode::F(1))]),
+                (ChatInput, Help, vec![key(KeyCode::F(1))]),
+                (ExitConfirm, Help, vec![chr('?'), key(KeyCode::F(1))]),
+                (HelpOverlay, Help, vec![chr('?'), key(KeyCode::F(1))]),
+            ],
+        }
+    }
+}
+
+impl Keymap {
+    pub fn vim() -> Self {
+        let mut keymap = Keymap::default();
+        for context in [
+            KeyContext::RoomList,
+            KeyContext::RecentRooms,
+            KeyContext::MemberList,
+            KeyContext::HelpOverlay,
+        ] {
+            keymap.add(context, Action::Up, KeyBinding::new(KeyCode::Char('k')));
+            keymap.add(context, Action::Down, KeyBinding::new(KeyCode::Char('j')));
+        }
+        // 'k' moves up now
+        keymap.set(
+            KeyContext::MemberList,
+            Action::Kick,
+            vec![KeyBinding::new(KeyCode::Char('K'))],
+        );
+        keymap.add(
+            KeyContext::Entry,
+            Action::NextOption,
+            KeyBinding::new(KeyCode::Char('j')),
+        );
+        keymap.add(
+            KeyContext::ChatNormal,
+            Action::EnterInput,
+            KeyBinding::new(KeyCode::Char('a')),
+        );
+        keymap
+    }
+
+    pub fn emacs() -> Self {
+        let mut keymap = Keymap::default();
+        for context in [
+            KeyContext::RoomList,
+            KeyContext::RecentRooms,
+            KeyContext::MemberList,
+            KeyContext::HelpOverlay,
+        ] {
+            keymap.add(context, Action::Up, KeyBinding::ctrl('p'));
+            keymap.add(context, Action::Down, KeyBinding::ctrl('n'));
+        }
+        keymap.add(KeyContext::Entry, Action::NextOption, KeyBinding::ctrl('n'));
+        // C-g cancels wherever Esc does
+        for (_, _, keys) in keymap.bindings.iter_mut() {
+            if keys.contains(&KeyBinding::new(KeyCode::Esc)) {
+                keys.push(KeyBinding::ctrl('g'));
+            }
+        }
+        keymap.add(
+            KeyContext::ChatInput,
+            Action::Complete,
+            KeyBinding::alt('/'),
+        );
+        keymap.add(
+            KeyContext::ChatNormal,
+            Action::EnterInput,
+            KeyBinding::new(KeyCode::Enter),
+        );
+        keymap
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Keymap::default()),
+            "vim" => Some(Keymap::vim()),
+            "emacs" => Some(Keymap::emacs()),
+            _ => None,
+        }
+    }
+
+    // Starts from the chosen preset, then every action named in the config has its keys
+    // replaced in each context it appears in
+    pub fn from_config(config: &KeybindingsConfig) -> Result<Self, String> {
+        let mut keymap = match &config.preset {
+            Some(name) => Keymap::preset(name).ok_or(format!(
+                "keybindings.preset: unknown preset {}, expected one of {}",
+                name,
+                PRESET_NAMES.join(", ")
+            ))?,
+            None => Keymap::default(),
+        };
+
+        for (name, spec) in config.bindings.iter() {
+            let action =
+                Action::from_str(name).ok_or(format!("keybindings.{}: unknown action", name))?;
+            let keys = match spec {
+                KeySpec::One(key) => vec![key.clone()],
+                KeySpec::Many(keys) => keys.clone(),
+            };
+            let keys = keys
+                .iter()
+                .map(|key| {
+                    KeyBinding::parse(key)
+                        .ok_or(format!("keybindings.{}: unknown key {}", name, key))
+                })
+                .collect::<Result<Vec<KeyBinding>, String>>()?;
+            for (_, bound_action, bound_keys) in keymap.bindings.iter_mut() {
+                if *bound_action == action {
+                    *bound_keys = keys.clone();
+                }
+            }
+        }
+
+        keymap.check()?;
+        Ok(keymap)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        for (idx, (context, action, keys)) in self.bindings.iter().enumerate() {
+            for key in keys {
+                if context.takes_text() && key.is_plain_char() {
+                    return Err(format!(
+                        "keybindings.{}: {} would stop you typing it in {}",
+                        action.as_str(),
+                        key,
+                        context.as_str()
+                    ));
+                }
+                if *context == KeyContext::RecentRooms
+                    && matches!(key.code, KeyCode::Char('1'..='9'))
+                    && key.modifiers.is_empty()
+                {
+                    return Err(format!(
+                        "keybindings.{}: {} already picks a recent room",
+                        action.as_str(),
+                        key
+                    ));
+                }
+                let clash = self.bindings[..idx]
+                    .iter()
+                    .find(|(other_context, _, other_keys)| {
+                        other_context == context && other_keys.contains(key)
+                    });
+                if let Some((_, other_action, _)) = clash {
+                    return Err(format!(
+                        "keybindings: {} is bound to both {} and {} in the {}",
+                        key,
+                        other_action.as_str(),
+                        action.as_str(),
+                        context.as_str()
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, context: KeyContext, action: Action, key: KeyBinding) {
+        if let Some((_, _, keys)) = self
+            .bindings
+            .iter_mut()
+            .find(|(c, a, _)| *c == context && *a == action)
+        {
+            keys.push(key);
+        }
+    }
+
+    fn set(&mut self, context: KeyContext, action: Action, new_keys: Vec<KeyBinding>) {
+        if let Some((_, _, keys)) = self
+            .bindings
+            .iter_mut()
+            .find(|(c, a, _)| *c == context && *a == action)
+        {
+            *keys = new_keys;
+        }
+    }
+
+    pub fn action(&self, context: KeyContext, key: &KeyEvent) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(c, _, keys)| *c == context && keys.iter().any(|k| k.matches(key)))
+            .map(|(_, action, _)| *action)
+    }
+
+    // The first key bound to the action, so a mouse click can be handled like that key press
+    pub fn key_event(&self, context: KeyContext, action: Action) -> Option<KeyEvent> {
+        self.bindings
+            .iter()
+            .find(|(c, a, _)| *c == context && *a == action)
+            .and_then(|(_, _, keys)| keys.first())
+            .map(|key| KeyEvent::new(key.code, key.modifiers))
+    }
+
+    // Every action bound in the context with its keys, in the order they were defined
+    pub fn bindings(&self, context: KeyContext) -> Vec<(Action, String)> {
+        self.bindings
+            .iter()
+            .filter(|(c, _, keys)| *c == context && !keys.is_empty())
+            .map(|(_, action, _)| (*action, self.keys(context, *action)))
+            .collect()
+    }
+
+    // The keys for an action joined for the instruction panels, like "Up/'k'"
+    pub fn keys(&self, context: KeyContext, action: Action) -> String {
+        self.bindings
+            .iter()
+            .find(|(c, a, _)| *c == context && *a == action)
+            .map(|(_, _, keys)| {
+                keys.iter()
+                    .map(|key| key.to_string())
+                    .collect::<Vec<String>>()
+                    .join("/")
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap_from(toml: &str) -> Result<Keymap, String> {
+        Keymap::from_config(&toml::from_str::<KeybindingsConfig>(toml).unwrap())
+    }
+
+    #[test]
+    fn parses_key_bindings() {
+        assert_eq!(
+            KeyBinding::parse("i"),
+            Some(KeyBinding::new(KeyCode::Char('i')))
+        );
+        assert_eq!(
+            KeyBinding::parse("Space"),
+            Some(KeyBinding::new(KeyCode::Char(' ')))
+        );
+        assert_eq!(
+            KeyBinding::parse("Esc"),
+            Some(KeyBinding::new(KeyCode::Esc))
+        );
+        assert_eq!(
+            KeyBinding::parse("F12"),
+            Some(KeyBinding::new(KeyCode::F(12)))
+        );
+        assert_eq!(KeyBinding::parse("Ctrl-n"), Some(KeyBinding::ctrl('n')));
+        assert_eq!(KeyBinding::parse("M-x"), Some(KeyBinding::alt('x')));
+        assert_eq!(
+            KeyBinding::parse("C-M-Up"),
+            Some(KeyBinding {
+                code: KeyCode::Up,
+                modifiers: KeyModifiers::CONTROL | KeyModifiers::ALT,
+            })
+        );
+        assert_eq!(KeyBinding::parse("F13"), None);
+        assert_eq!(KeyBinding::parse("Shift-a"), None);
+        assert_eq!(KeyBinding::parse(""), None);
+    }
+
+    #[test]
+    fn accepts_a_rebinding() {
+        let keymap = keymap_from("preset = \"vim\"\nenter_input = \"a\"").unwrap();
+        assert_eq!(
+            keymap.keys(KeyContext::ChatNormal, Action::EnterInput),
+            "'a'"
+        );
+    }
+
+    #[test]
+    fn rejects_a_key_bound_twice_in_one_context() {
+        let err = keymap_from("enter_input = \"q\"").unwrap_err();
+        assert!(err.contains("is bound to both"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_a_plain_char_in_a_form() {
+        let err = keymap_from("submit = \"x\"").unwrap_err();
+        assert!(err.contains("would stop you typing it in forms"), "{}", err);
+        assert!(keymap_from("submit = \"Ctrl-x\"").is_ok());
+    }
+
+    #[test]
+    fn toggle_password_is_rebound_on_its_own() {
+        let keymap = keymap_from("toggle_password = \"Ctrl-r\"").unwrap();
+        assert_eq!(
+            keymap.keys(KeyContext::PasswordForm, Action::TogglePassword),
+            "Ctrl-r"
+        );
+        assert_eq!(keymap.keys(KeyContext::Form, Action::NextField), "Tab");
+    }
+}
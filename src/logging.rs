@@ -0,0 +1,48 @@
+use crate::command_parser::LogLevel;
+use std::io::Error;
+use std::path::PathBuf;
+use tracing::Level;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{Builder, Rotation};
+
+// One file per day, the oldest are deleted past this
+const MAX_LOG_FILES: usize = 7;
+
+impl From<LogLevel> for Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => Level::ERROR,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Trace => Level::TRACE,
+        }
+    }
+}
+
+pub fn log_dir() -> PathBuf {
+    dirs::data_dir()
+        .map(|dir| dir.join("chat_client"))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("logs")
+}
+
+// The TUI owns the terminal, so everything goes to a file instead.
+// Lines are written from a background thread and the guard flushes the rest when dropped.
+pub fn init(level: LogLevel) -> Result<WorkerGuard, Error> {
+    let appender = Builder::new()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("chat_client")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(log_dir())
+        .map_err(Error::other)?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_ansi(false)
+        .with_max_level(Level::from(level))
+        .try_init()
+        .map_err(Error::other)?;
+    Ok(guard)
+}
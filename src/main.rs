@@ -1,100 +1,187 @@
 mod app;
+mod chat_command;
 mod client;
 mod command_parser;
+mod config;
+mod keymap;
+mod logging;
+mod state;
+mod theme;
 mod ui;
 mod util;
 
+use std::backtrace::Backtrace;
 use std::collections::VecDeque;
 use std::fmt::Debug;
-use std::io::{stdin, Error};
+use std::io::{stdin, Error, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 // use std::net::TcpStream;
 use crate::app::*;
+use crate::chat_command::{completion_candidates, parse_chat_command, ChatCommand, ModAction};
 use crate::client::Client;
-use crate::command_parser::{Args, Operation};
+use crate::command_parser::Args;
+use crate::config::{Config, NotificationConfig};
+use crate::keymap::{Action, KeyContext, Keymap};
+use crate::state::{RecentRoom, State};
+use crate::theme::Theme;
 use crate::ui::*;
 use crate::util::read_buf;
+use crate::util::{
+    has_whitespace, is_valid_invite_code, is_valid_room_id, is_valid_string,
+    is_valid_string_with_whitespace,
+};
 use clap::Parser;
-use once_cell::sync::Lazy;
 use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::crossterm::cursor::Show;
 use ratatui::crossterm::event::{
     self, poll, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+    MouseButton, MouseEvent, MouseEventKind,
 };
 use ratatui::crossterm::execute;
 use ratatui::crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
+use ratatui::layout::{Position, Rect};
 use ratatui::Terminal;
-use regex::Regex;
 use tokio::net::TcpStream;
 use tokio::select;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+use zeroize::Zeroize;
 #[macro_use]
 extern crate dotenv_codegen;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let remote_server = dotenv!("REMOTE_SERVER");
-
-    // let args = Args::parse();
-
-    // #[allow(unused_assignments)]
-    // let mut client: Option<Sender<String>> = None;
-
-    // match &args.operation {
-    //     Operation::Create { username } => {
-    //         client = Some(Client::create_room(username.clone()).await?);
-    //     }
-    //     Operation::Join { username, room_id } => {
-    //         client = Some(Client::enter_room(username.clone(), room_id.clone()).await?);
-    //     }
-    // }
-
-    // if let Some(client) = client {
-    //     loop {
-    //         let mut user_input = String::new();
-    //         let _ = stdin().read_line(&mut user_input)?;
-
-    //         let _ = client.send(user_input.clone()).await;
-    //     }
-    // } else {
-    //     println!("Something went wrong");
-    //     Ok(())
-    // }
+    let args = Args::parse();
+
+    // Held until main returns, dropping it flushes whatever is still buffered
+    let _log_guard = match args.log_level {
+        Some(level) => match logging::init(level) {
+            Ok(guard) => Some(guard),
+            Err(e) => {
+                eprintln!(
+                    "Could not start logging to {}: {}",
+                    logging::log_dir().display(),
+                    e
+                );
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Report a broken config before the terminal is taken over
+    if let Some(path) = args.config.as_ref().filter(|path| !path.exists()) {
+        eprintln!("Config file {} does not exist", path.display());
+        std::process::exit(1);
+    }
+    let config_path = args.config.or_else(Config::default_path);
+    let config = match &config_path {
+        Some(path) => match Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Invalid config file {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => Config::default(),
+    };
+
+    let remote_server = match &args.server {
+        Some(name) => match config.server(name) {
+            Some(server) => server.address.clone(),
+            None => {
+                eprintln!("No server named {} in the config file", name);
+                std::process::exit(1);
+            }
+        },
+        None => match config.servers.first() {
+            Some(server) => server.address.clone(),
+            None => dotenv!("REMOTE_SERVER").to_string(),
+        },
+    };
+
+    // A panic would otherwise leave the shell in raw mode on the alternate screen
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let report = format!("{}\n\n{}", info, Backtrace::force_capture());
+        error!("{}", info);
+        let log_path = write_crash_log(&report);
+        // Tasks on the runtime's worker threads can panic while the TUI keeps running,
+        // so only the main thread gets the terminal back
+        if std::thread::current().name() == Some("main") {
+            restore_terminal();
+            default_hook(info);
+            if let Some(path) = log_path {
+                eprintln!("Crash report written to {}", path.display());
+            }
+        }
+    }));
 
     enable_raw_mode()?;
+    let _terminal_guard = TerminalGuard;
     let mut stderr = std::io::stderr(); // This is a special case. Normally using stdout is fine
     execute!(stderr, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stderr);
     let mut terminal = Terminal::new(backend)?;
 
     let mut app = App::new();
-    let res = run_app(&mut terminal, &mut app, remote_server).await;
-
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    app.room.ignored_users = Arc::new(Mutex::new(
+        config
+            .ignore
+            .get(&remote_server)
+            .cloned()
+            .unwrap_or_default(),
+    ));
+    app.record_size = config.history_size;
+    // Patterns were already checked when the config was loaded
+    app.room.highlight_rules = Arc::new(config.highlight.compile().unwrap_or_default());
+    // https://no-color.org: any non-empty value turns colors off
+    let no_color = std::env::var("NO_COLOR").is_ok_and(|value| !value.is_empty());
+    app.theme = Theme::from_config(&config.theme, no_color).unwrap_or_default();
+    app.keymap = Keymap::from_config(&config.keybindings).unwrap_or_default();
+    app.config = config;
+    app.config_path = config_path;
+    app.state_path = State::default_path();
+    if let Some(path) = &app.state_path {
+        app.state = State::load(path);
+    }
+    app.username = app.default_username();
+    info!(server = %remote_server, config = ?app.config_path, "starting");
+    let res = run_app(&mut terminal, &mut app, &remote_server).await;
+    if let Err(e) = &res {
+        error!(error = %e, "stopped on a terminal error");
+    }
 
     // dbg!(app);
 
     Ok(())
 }
 
-async fn run_app<B: Backend>(
+async fn run_app<B: Backend + Write>(
     terminal: &mut Terminal<B>,
     app: &mut App,
     remote_server: &str,
 ) -> std::io::Result<()> {
+    let mut last_screen = None;
     loop {
-        let chat_room_record_arc = app.chat_room_record.clone();
-        let chat_member_arc = app.chat_room_member.clone();
+        let screen = std::mem::discriminant(&app.current_screen);
+        if last_screen != Some(screen) {
+            debug!(screen = ?app.current_screen, "screen changed");
+            last_screen = Some(screen);
+        }
+
+        let chat_room_record_arc = app.room.chat_room_record.clone();
+        let chat_member_arc = app.room.chat_room_member.clone();
 
         let chat_room_record_handle = chat_room_record_arc.lock().await;
         let chat_room_record = chat_room_record_handle.clone();
+        let record_total = *app.room.record_total.lock().await;
         drop(chat_room_record_handle);
         // let chat_room_record = chat_room_record.into_iter().rev().collect::<Vec<String>>();
 
@@ -102,79 +189,192 @@ async fn run_app<B: Backend>(
         let chat_members = chat_room_member_handle.clone();
         drop(chat_room_member_handle);
 
-        let abandon_arc = app.abandon.clone();
+        let abandon_arc = app.room.abandon.clone();
         let abandon_handle = abandon_arc.lock().await;
         if *abandon_handle {
             // return Ok(());
+            let kick_reason = app.room.kick_reason.lock().await.take();
+            info!(reason = ?kick_reason, "chat connection closed");
             app.reinitialize();
+            app.entry_notice = kick_reason;
         }
         drop(abandon_handle);
 
-        terminal.draw(|f| ui(f, app, chat_room_record, chat_members))?;
+        // A scrolled up view stays on the same messages while new ones arrive
+        if app.chat_scroll > 0 {
+            app.chat_scroll = (app.chat_scroll
+                + record_total.saturating_sub(app.seen_record_total))
+            .min(chat_room_record.len().saturating_sub(1));
+        }
+        app.seen_record_total = record_total;
+
+        let mention_count = *app.room.mention_count.lock().await;
+        if mention_count > app.notified_mentions {
+            app.notified_mentions = mention_count;
+            notify_mention(
+                terminal.backend_mut(),
+                &app.room_name,
+                &app.config.notifications,
+            )?;
+        }
+        // Mentions count as read whenever the newest messages are on screen
+        if matches!(app.current_screen, CurrentScreen::Chat) && app.chat_scroll == 0 {
+            app.clear_mentions().await;
+        }
+
+        let room_topic = app.room.room_topic.lock().await.clone();
+        let ignored_users = app.room.ignored_users.lock().await.clone();
+
+        let record_len = chat_room_record.len();
+        let mut click_areas = ClickAreas::default();
+        terminal.draw(|f| {
+            click_areas = ui(
+                f,
+                app,
+                chat_room_record,
+                chat_members,
+                room_topic,
+                ignored_users,
+                remote_server,
+            )
+        })?;
+        app.click_areas = click_areas;
 
         if !poll(std::time::Duration::from_millis(350))? {
             continue;
         }
 
-        if let Event::Key(key) = event::read()? {
+        // Clicks come back as the key they stand for, so they go through the same handling
+        let event = match event::read()? {
+            Event::Mouse(mouse) => match mouse_to_key(app, mouse, record_len) {
+                Some(key) => Event::Key(key),
+                None => continue,
+            },
+            // Redraw at the new size right away rather than on the next poll timeout
+            Event::Resize(_, _) => {
+                terminal.autoresize()?;
+                continue;
+            }
+            event => event,
+        };
+
+        if let Event::Key(key) = event {
             if key.kind == KeyEventKind::Release {
                 continue;
             }
 
+            if app.show_help {
+                let size = terminal.size()?;
+                let help_height = help_area(Rect::new(0, 0, size.width, size.height)).height;
+                let max_scroll =
+                    (help_lines(app).len() as u16).saturating_sub(help_height.saturating_sub(2));
+                match (app.keymap.action(KeyContext::HelpOverlay, &key), key.code) {
+                    (Some(Action::Up), _) => {
+                        app.help_scroll = app.help_scroll.min(max_scroll).saturating_sub(1);
+                    }
+                    (Some(Action::Down), _) => {
+                        app.help_scroll = (app.help_scroll + 1).min(max_scroll);
+                    }
+                    (None, KeyCode::PageUp) => {
+                        app.help_scroll = app.help_scroll.min(max_scroll).saturating_sub(10);
+                    }
+                    (None, KeyCode::PageDown) => {
+                        app.help_scroll = (app.help_scroll + 10).min(max_scroll);
+                    }
+                    (Some(Action::Back), _) | (Some(Action::Help), _) => {
+                        app.show_help = false;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            if app.keymap.action(app.key_context(), &key) == Some(Action::Help) {
+                app.show_help = true;
+                app.help_scroll = 0;
+                continue;
+            }
+
             // if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('q') {
             //     return Ok(());
             // }
 
             match app.current_screen {
-                CurrentScreen::Entry => match key.code {
-                    KeyCode::Tab => match app.current_selection {
+                CurrentScreen::Entry => match app.keymap.action(KeyContext::Entry, &key) {
+                    _ if app.entry_notice.is_some() => {
+                        app.entry_notice = None;
+                    }
+                    Some(Action::NextOption) => match app.current_selection {
                         CurrentSelection::Create => app.current_selection = CurrentSelection::Join,
-                        CurrentSelection::Join => app.current_selection = CurrentSelection::Create,
+                        CurrentSelection::Join => app.current_selection = CurrentSelection::Recent,
+                        CurrentSelection::Recent => {
+                            app.current_selection = CurrentSelection::Create
+                        }
                     },
-                    KeyCode::Enter => match app.current_selection {
+                    Some(Action::Select) => match app.current_selection {
                         CurrentSelection::Create => app.current_screen = CurrentScreen::Create,
                         CurrentSelection::Join => app.current_screen = CurrentScreen::Join,
+                        CurrentSelection::Recent => {
+                            app.recent_idx = 0;
+                            app.current_screen = CurrentScreen::RecentRooms;
+                        }
                     },
-                    KeyCode::Char('q') => {
+                    Some(Action::Quit) => {
                         break;
                     }
                     _ => {}
                 },
                 CurrentScreen::Create => {
                     if app.password_prompt {
-                        match key.code {
-                            KeyCode::Esc => {
+                        match app.keymap.action(KeyContext::CreatePrompt, &key) {
+                            Some(Action::Back) => {
                                 app.password_prompt = false;
                                 app.create_room_error = None;
                             }
-                            KeyCode::Char('y') => {
+                            Some(Action::SetPassword) => {
                                 app.current_screen = CurrentScreen::CreatePassword;
                                 app.create_room_error = None;
                             }
-                            KeyCode::Char('n') => {
-                                if let Ok((msg_pipe, room_id)) = Client::create_room(
+                            Some(Action::TogglePrivate) => {
+                                app.private_room = !app.private_room;
+                            }
+                            Some(Action::NoPassword) => {
+                                match Client::create_room(
                                     app.username.clone(),
                                     app.room_name.clone(),
                                     None,
-                                    app.chat_room_record.clone(),
-                                    app.chat_room_member.clone(),
-                                    app.abandon.clone(),
+                                    app.private_room,
+                                    app.room.clone(),
                                     app.record_size,
                                     remote_server,
                                 )
                                 .await
                                 {
-                                    app.msg_pipe = Some(msg_pipe);
-                                    app.enter_room(room_id);
-                                } else {
-                                    app.create_room_error = Some(CreateRoomError::ServerError);
+                                    Ok((msg_pipe, room_id, invite_code)) => {
+                                        app.msg_pipe = Some(msg_pipe);
+                                        app.invite_link = invite_code
+                                            .as_ref()
+                                            .map(|code| invite_link(remote_server, &room_id, code));
+                                        app.enter_room(room_id, remote_server, invite_code);
+                                    }
+                                    Err(e) => match e.kind() {
+                                        std::io::ErrorKind::AlreadyExists => {
+                                            app.password_prompt = false;
+                                            app.create_room_input = CreateRoomInput::Username;
+                                            app.create_room_error =
+                                                Some(CreateRoomError::UsernameTaken);
+                                        }
+                                        _ => {
+                                            app.create_room_error =
+                                                Some(CreateRoomError::ServerError);
+                                        }
+                                    },
                                 }
                             }
                             _ => {}
                         }
                     } else {
-                        match key.code {
-                            KeyCode::Backspace => match app.create_room_input {
+                        match (app.keymap.action(KeyContext::Form, &key), key.code) {
+                            (None, KeyCode::Backspace) => match app.create_room_input {
                                 CreateRoomInput::Username => {
                                     app.username.pop();
                                     app.create_room_error = None;
@@ -184,7 +384,7 @@ async fn run_app<B: Backend>(
                                     app.create_room_error = None
                                 }
                             },
-                            KeyCode::Tab => match app.create_room_input {
+                            (Some(Action::NextField), _) => match app.create_room_input {
                                 CreateRoomInput::Username => {
                                     app.create_room_input = CreateRoomInput::RoomName
                                 }
@@ -192,7 +392,7 @@ async fn run_app<B: Backend>(
                                     app.create_room_input = CreateRoomInput::Username
                                 }
                             },
-                            KeyCode::Enter => {
+                            (Some(Action::Submit), _) => {
                                 if app.username.len() < 1 || app.username.len() > 50 {
                                     app.create_room_error =
                                         Some(CreateRoomError::InvalidUsernameLength);
@@ -216,7 +416,7 @@ async fn run_app<B: Backend>(
                                 }
                                 app.password_prompt = true;
                             }
-                            KeyCode::Char(value) => match app.create_room_input {
+                            (None, KeyCode::Char(value)) => match app.create_room_input {
                                 CreateRoomInput::Username => {
                                     app.username.push(value);
                                     app.create_room_error = None;
@@ -226,8 +426,8 @@ async fn run_app<B: Backend>(
                                     app.create_room_error = None;
                                 }
                             },
-                            KeyCode::Esc => {
-                                app.username.clear();
+                            (Some(Action::Back), _) => {
+                                app.username = app.default_username();
                                 app.room_name.clear();
                                 app.create_room_error = None;
                                 app.current_screen = CurrentScreen::Entry;
@@ -237,208 +437,512 @@ async fn run_app<B: Backend>(
                         }
                     }
                 }
-                CurrentScreen::CreatePassword => match key.code {
-                    KeyCode::Char(value) => {
-                        app.password.push(value);
-                        app.create_room_error = None;
-                    }
-                    KeyCode::Backspace => {
-                        app.password.pop();
-                        app.create_room_error = None;
-                    }
-                    KeyCode::Esc => {
-                        app.password.clear();
-                        app.current_screen = CurrentScreen::Create;
-                        app.create_room_error = None;
-                        app.password_prompt = false;
-                    }
-                    KeyCode::Enter => {
-                        if app.password.len() < 4 || app.password.len() > 20 {
-                            app.create_room_error = Some(CreateRoomError::InvalidPasswordChar);
-                            continue;
-                        }
-
-                        if has_whitespace(&app.password) {
-                            app.create_room_error = Some(CreateRoomError::InvalidPasswordChar);
-                            continue;
-                        }
-
-                        if let Ok((msg_pipe, room_id)) = Client::create_room(
-                            app.username.clone(),
-                            app.room_name.clone(),
-                            Some(app.password.clone()),
-                            app.chat_room_record.clone(),
-                            app.chat_room_member.clone(),
-                            app.abandon.clone(),
-                            app.record_size,
-                            remote_server,
-                        )
-                        .await
-                        {
-                            app.msg_pipe = Some(msg_pipe);
-                            app.enter_room(room_id);
-                        } else {
-                            app.create_room_error = Some(CreateRoomError::ServerError);
+                CurrentScreen::CreatePassword => {
+                    match (app.keymap.action(KeyContext::PasswordForm, &key), key.code) {
+                        (Some(Action::TogglePassword), _) => {
+                            app.show_password = !app.show_password;
                         }
-                    }
-                    _ => {}
-                },
-                CurrentScreen::Join => match key.code {
-                    KeyCode::Backspace => {
-                        app.join_room_error = None;
-                        app.username.pop();
-                    }
-                    KeyCode::Char(value) => {
-                        app.join_room_error = None;
-                        app.username.push(value);
-                    }
-                    KeyCode::Enter => {
-                        if app.username.len() < 1 || app.username.len() > 50 {
-                            app.join_room_error = Some(JoinRoomError::InvalidUsernameLength);
-                            continue;
+                        (None, KeyCode::Char(value)) => {
+                            app.password.push(value);
+                            app.create_room_error = None;
                         }
-
-                        if !is_valid_string(&app.username) {
-                            app.join_room_error = Some(JoinRoomError::InvalidUsername);
-                            continue;
+                        (None, KeyCode::Backspace) => {
+                            app.password.pop();
+                            app.create_room_error = None;
                         }
-
-                        if let Ok(room_lst) = Client::get_room_list(remote_server).await {
-                            app.room_lst = room_lst;
-                        } else {
-                            app.join_room_error = Some(JoinRoomError::GetRoomListFailed);
-                            continue;
+                        (Some(Action::Back), _) => {
+                            app.password.zeroize();
+                            app.show_password = false;
+                            app.current_screen = CurrentScreen::Create;
+                            app.create_room_error = None;
+                            app.password_prompt = false;
                         }
+                        (Some(Action::Submit), _) => {
+                            if app.password.len() < 4 || app.password.len() > 20 {
+                                app.create_room_error = Some(CreateRoomError::InvalidPasswordChar);
+                                continue;
+                            }
 
-                        if app.room_lst.len() != 0 {
-                            app.current_screen = CurrentScreen::RoomSelect;
-                        } else {
-                            app.join_room_error = Some(JoinRoomError::ZeroRooms);
-                        }
-                    }
-                    KeyCode::Esc => {
-                        app.username.clear();
-                        app.join_room_error = None;
-                        app.current_screen = CurrentScreen::Entry;
-                    }
-                    _ => {}
-                },
-                CurrentScreen::RoomSelect => match key.code {
-                    KeyCode::Enter => {
-                        let select_room = app.room_lst[app.room_idx].clone();
-
-                        if select_room.2 {
-                            app.current_screen = CurrentScreen::PasswordCheck;
-                        } else {
-                            if let Ok((msg_pipe, room_id)) = Client::enter_room(
+                            if has_whitespace(&app.password) {
+                                app.create_room_error = Some(CreateRoomError::InvalidPasswordChar);
+                                continue;
+                            }
+
+                            match Client::create_room(
                                 app.username.clone(),
-                                select_room.0,
-                                None,
-                                app.chat_room_record.clone(),
-                                app.chat_room_member.clone(),
-                                app.abandon.clone(),
+                                app.room_name.clone(),
+                                Some(app.password.clone()),
+                                app.private_room,
+                                app.room.clone(),
                                 app.record_size,
                                 remote_server,
                             )
                             .await
                             {
-                                app.msg_pipe = Some(msg_pipe);
-                                app.room_name = select_room.1;
-                                app.enter_room(room_id);
-                            } else {
-                                app.join_room_error = Some(JoinRoomError::RoomNotFound)
+                                Ok((msg_pipe, room_id, invite_code)) => {
+                                    app.msg_pipe = Some(msg_pipe);
+                                    app.invite_link = invite_code
+                                        .as_ref()
+                                        .map(|code| invite_link(remote_server, &room_id, code));
+                                    app.enter_room(room_id, remote_server, invite_code);
+                                }
+                                Err(e) => match e.kind() {
+                                    std::io::ErrorKind::AlreadyExists => {
+                                        app.password.zeroize();
+                                        app.password_prompt = false;
+                                        app.create_room_input = CreateRoomInput::Username;
+                                        app.create_room_error =
+                                            Some(CreateRoomError::UsernameTaken);
+                                        app.current_screen = CurrentScreen::Create;
+                                    }
+                                    _ => {
+                                        app.create_room_error = Some(CreateRoomError::ServerError);
+                                    }
+                                },
                             }
                         }
+                        _ => {}
                     }
-                    KeyCode::Esc => {
-                        app.current_screen = CurrentScreen::Join;
-                        app.join_room_error = None;
-                    }
-                    KeyCode::Char('r') => {
-                        app.join_room_error = None;
-                        if let Ok(room_lst) = Client::get_room_list(remote_server).await {
-                            if room_lst.len() != 0 {
+                }
+                CurrentScreen::Join => {
+                    match (app.keymap.action(KeyContext::Form, &key), key.code) {
+                        (Some(Action::NextField), _) => match app.join_room_input {
+                            JoinRoomInput::Username => app.join_room_input = JoinRoomInput::RoomId,
+                            JoinRoomInput::RoomId => app.join_room_input = JoinRoomInput::Username,
+                        },
+                        (None, KeyCode::Backspace) => {
+                            app.join_room_error = None;
+                            match app.join_room_input {
+                                JoinRoomInput::Username => app.username.pop(),
+                                JoinRoomInput::RoomId => app.room_id.pop(),
+                            };
+                        }
+                        (None, KeyCode::Char(value)) => {
+                            app.join_room_error = None;
+                            match app.join_room_input {
+                                JoinRoomInput::Username => app.username.push(value),
+                                JoinRoomInput::RoomId => app.room_id.push(value),
+                            }
+                        }
+                        (Some(Action::Submit), _) => {
+                            if app.username.len() < 1 || app.username.len() > 50 {
+                                app.join_room_error = Some(JoinRoomError::InvalidUsernameLength);
+                                continue;
+                            }
+
+                            if !is_valid_string(&app.username) {
+                                app.join_room_error = Some(JoinRoomError::InvalidUsername);
+                                continue;
+                            }
+
+                            if !app.room_id.is_empty() {
+                                let (target_id, invite_code) =
+                                    match parse_room_target(&app.room_id, remote_server) {
+                                        Ok(target) => target,
+                                        Err(e) => {
+                                            app.join_room_error = Some(e);
+                                            continue;
+                                        }
+                                    };
+
+                                match Client::enter_room(
+                                    app.username.clone(),
+                                    target_id.clone(),
+                                    invite_code.clone(),
+                                    None,
+                                    app.room.clone(),
+                                    app.record_size,
+                                    remote_server,
+                                )
+                                .await
+                                {
+                                    Ok((msg_pipe, joined_room)) => {
+                                        app.msg_pipe = Some(msg_pipe);
+                                        app.room_name = joined_room.room_name.unwrap_or_default();
+                                        app.enter_room(
+                                            joined_room.room_id,
+                                            remote_server,
+                                            invite_code,
+                                        );
+                                    }
+                                    Err(e) => match e.kind() {
+                                        std::io::ErrorKind::PermissionDenied
+                                        | std::io::ErrorKind::InvalidInput => {
+                                            app.password_target = Some(PasswordTarget {
+                                                room_id: target_id,
+                                                room_name: String::new(),
+                                                invite_code,
+                                                back_to: CurrentScreen::Join,
+                                            });
+                                            app.current_screen = CurrentScreen::PasswordCheck;
+                                        }
+                                        std::io::ErrorKind::AlreadyExists => {
+                                            app.join_room_error =
+                                                Some(JoinRoomError::UsernameTaken);
+                                        }
+                                        _ => {
+                                            app.join_room_error = Some(JoinRoomError::RoomNotFound)
+                                        }
+                                    },
+                                }
+                                continue;
+                            }
+
+                            if let Ok(room_lst) = Client::get_room_list(remote_server).await {
                                 app.room_lst = room_lst;
+                            } else {
+                                app.join_room_error = Some(JoinRoomError::GetRoomListFailed);
+                                continue;
+                            }
+
+                            if app.room_lst.len() != 0 {
+                                app.current_screen = CurrentScreen::RoomSelect;
+                            } else {
+                                app.join_room_error = Some(JoinRoomError::ZeroRooms);
+                            }
+                        }
+                        (Some(Action::Back), _) => {
+                            app.username = app.default_username();
+                            app.room_id.clear();
+                            app.join_room_error = None;
+                            app.join_room_input = JoinRoomInput::Username;
+                            app.current_screen = CurrentScreen::Entry;
+                        }
+                        _ => {}
+                    }
+                }
+                CurrentScreen::RoomSelect => {
+                    if app.room_filter_editing {
+                        match (app.keymap.action(KeyContext::Form, &key), key.code) {
+                            (None, KeyCode::Char(value)) => {
+                                app.room_filter.push(value);
+                                app.room_idx = 0;
+                            }
+                            (None, KeyCode::Backspace) => {
+                                app.room_filter.pop();
                                 app.room_idx = 0;
+                            }
+                            (Some(Action::Submit), _) => {
+                                app.room_filter_editing = false;
+                            }
+                            (Some(Action::Back), _) => {
+                                app.room_filter.clear();
+                                app.room_filter_editing = false;
+                                app.room_idx = 0;
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+
+                    match app.keymap.action(KeyContext::RoomList, &key) {
+                        Some(Action::Select) => {
+                            let select_room = match app.selected_room() {
+                                Some(room) => room,
+                                None => continue,
+                            };
+
+                            if select_room.has_password {
+                                app.password_target = Some(PasswordTarget {
+                                    room_id: select_room.id,
+                                    room_name: select_room.name,
+                                    invite_code: None,
+                                    back_to: CurrentScreen::RoomSelect,
+                                });
+                                app.current_screen = CurrentScreen::PasswordCheck;
+                            } else {
+                                match Client::enter_room(
+                                    app.username.clone(),
+                                    select_room.id.clone(),
+                                    None,
+                                    None,
+                                    app.room.clone(),
+                                    app.record_size,
+                                    remote_server,
+                                )
+                                .await
+                                {
+                                    Ok((msg_pipe, joined_room)) => {
+                                        app.msg_pipe = Some(msg_pipe);
+                                        app.room_name =
+                                            joined_room.room_name.unwrap_or(select_room.name);
+                                        app.enter_room(joined_room.room_id, remote_server, None);
+                                    }
+                                    Err(e) => match e.kind() {
+                                        std::io::ErrorKind::PermissionDenied => {
+                                            app.password_target = Some(PasswordTarget {
+                                                room_id: select_room.id,
+                                                room_name: select_room.name,
+                                                invite_code: None,
+                                                back_to: CurrentScreen::RoomSelect,
+                                            });
+                                            app.current_screen = CurrentScreen::PasswordCheck;
+                                        }
+                                        std::io::ErrorKind::AlreadyExists => {
+                                            app.join_room_error =
+                                                Some(JoinRoomError::UsernameTaken);
+                                            app.current_screen = CurrentScreen::Join;
+                                        }
+                                        _ => {
+                                            app.join_room_error = Some(JoinRoomError::RoomNotFound)
+                                        }
+                                    },
+                                }
+                            }
+                        }
+                        Some(Action::Back) => {
+                            app.current_screen = CurrentScreen::Join;
+                            app.join_room_error = None;
+                        }
+                        Some(Action::ReloadRooms) => {
+                            app.join_room_error = None;
+                            if let Ok(room_lst) = Client::get_room_list(remote_server).await {
+                                if room_lst.len() != 0 {
+                                    app.room_lst = room_lst;
+                                    app.room_idx = 0;
+                                } else {
+                                    app.room_lst.clear();
+                                    app.room_idx = 0;
+                                    app.join_room_error = Some(JoinRoomError::ZeroRooms);
+                                    app.current_screen = CurrentScreen::Join;
+                                }
                             } else {
                                 app.room_lst.clear();
                                 app.room_idx = 0;
-                                app.join_room_error = Some(JoinRoomError::ZeroRooms);
+                                app.join_room_error = Some(JoinRoomError::GetRoomListFailed);
                                 app.current_screen = CurrentScreen::Join;
                             }
-                        } else {
-                            app.room_lst.clear();
+                        }
+                        Some(Action::Up) => {
+                            app.join_room_error = None;
+                            match app.room_idx.checked_sub(1) {
+                                Some(val) => app.room_idx = val,
+                                None => app.room_idx = 0,
+                            }
+                        }
+                        Some(Action::Down) => {
+                            app.join_room_error = None;
+                            if app.filtered_rooms().len() > app.room_idx + 1 {
+                                app.room_idx += 1;
+                            }
+                        }
+                        Some(Action::Filter) => {
+                            app.join_room_error = None;
+                            app.room_filter_editing = true;
+                        }
+                        Some(Action::OpenOnly) => {
+                            app.room_kind_filter = match app.room_kind_filter {
+                                RoomKindFilter::OpenOnly => RoomKindFilter::All,
+                                _ => RoomKindFilter::OpenOnly,
+                            };
                             app.room_idx = 0;
-                            app.join_room_error = Some(JoinRoomError::GetRoomListFailed);
-                            app.current_screen = CurrentScreen::Join;
                         }
-                    }
-                    KeyCode::Up => {
-                        app.join_room_error = None;
-                        match app.room_idx.checked_sub(1) {
-                            Some(val) => app.room_idx = val,
-                            None => app.room_idx = 0,
+                        Some(Action::PasswordOnly) => {
+                            app.room_kind_filter = match app.room_kind_filter {
+                                RoomKindFilter::PasswordOnly => RoomKindFilter::All,
+                                _ => RoomKindFilter::PasswordOnly,
+                            };
+                            app.room_idx = 0;
                         }
-                    }
-                    KeyCode::Down => {
-                        app.join_room_error = None;
-                        if app.room_lst.len() > app.room_idx + 1 {
-                            app.room_idx += 1;
+                        Some(Action::CycleSort) => {
+                            app.room_sort = match app.room_sort {
+                                RoomSort::ServerOrder => RoomSort::Name,
+                                RoomSort::Name => RoomSort::Members,
+                                RoomSort::Members => RoomSort::Newest,
+                                RoomSort::Newest => RoomSort::ServerOrder,
+                            };
+                            app.room_idx = 0;
                         }
+                        _ => {}
                     }
-                    _ => {}
-                },
-                CurrentScreen::PasswordCheck => match key.code {
-                    KeyCode::Esc => {
-                        app.current_screen = CurrentScreen::RoomSelect;
-                        app.check_passwork.clear();
-                        app.join_room_error = None;
-                    }
-                    KeyCode::Char(value) => {
-                        app.check_passwork.push(value);
-                        app.join_room_error = None;
+                }
+                CurrentScreen::RecentRooms => {
+                    let recent_rooms = app
+                        .state
+                        .recent_rooms_on(remote_server)
+                        .into_iter()
+                        .cloned()
+                        .collect::<Vec<RecentRoom>>();
+                    // Digits pick a room directly, Enter picks the highlighted one
+                    let picked = match (app.keymap.action(KeyContext::RecentRooms, &key), key.code)
+                    {
+                        (Some(Action::Up), _) => {
+                            app.recent_idx = app.recent_idx.saturating_sub(1);
+                            None
+                        }
+                        (Some(Action::Down), _) => {
+                            if app.recent_idx + 1 < recent_rooms.len() {
+                                app.recent_idx += 1;
+                            }
+                            None
+                        }
+                        (Some(Action::Forget), _) => {
+                            if let Some(room) = recent_rooms.get(app.recent_idx) {
+                                app.state.forget_room(&room.id, remote_server);
+                                if let Some(path) = &app.state_path {
+                                    let _ = app.state.save(path);
+                                }
+                                app.recent_idx = app.recent_idx.saturating_sub(1);
+                            }
+                            None
+                        }
+                        (Some(Action::Back), _) => {
+                            app.join_room_error = None;
+                            app.current_screen = CurrentScreen::Entry;
+                            None
+                        }
+                        (Some(Action::Select), _) => recent_rooms.get(app.recent_idx),
+                        (_, KeyCode::Char(c @ '1'..='9')) => {
+                            recent_rooms.get(c.to_digit(10).unwrap() as usize - 1)
+                        }
+                        _ => None,
+                    };
+                    let room = match picked {
+                        Some(room) => room.clone(),
+                        None => continue,
+                    };
+
+                    // Without a usable remembered name, ask for one on the Join screen
+                    let username_error = if app.username.is_empty() || app.username.len() > 50 {
+                        Some(JoinRoomError::InvalidUsernameLength)
+                    } else if !is_valid_string(&app.username) {
+                        Some(JoinRoomError::InvalidUsername)
+                    } else {
+                        None
+                    };
+                    if username_error.is_some() {
+                        // Only the ID is filled in, the invite code stays off the screen. A private
+                        // room then needs its invite link pasted again.
+                        app.room_id = room.id;
+                        app.join_room_error = username_error;
+                        app.join_room_input = JoinRoomInput::Username;
+                        app.current_screen = CurrentScreen::Join;
+                        continue;
                     }
-                    KeyCode::Backspace => {
-                        app.check_passwork.pop();
-                        app.join_room_error = None;
+
+                    match Client::enter_room(
+                        app.username.clone(),
+                        room.id.clone(),
+                        room.invite_code.clone(),
+                        None,
+                        app.room.clone(),
+                        app.record_size,
+                        remote_server,
+                    )
+                    .await
+                    {
+                        Ok((msg_pipe, joined_room)) => {
+                            app.msg_pipe = Some(msg_pipe);
+                            app.room_name = joined_room.room_name.unwrap_or(room.name);
+                            app.enter_room(joined_room.room_id, remote_server, room.invite_code);
+                        }
+                        Err(e) => match e.kind() {
+                            std::io::ErrorKind::PermissionDenied
+                            | std::io::ErrorKind::InvalidInput => {
+                                app.join_room_error = None;
+                                app.password_target = Some(PasswordTarget {
+                                    room_id: room.id,
+                                    room_name: room.name,
+                                    invite_code: room.invite_code,
+                                    back_to: CurrentScreen::RecentRooms,
+                                });
+                                app.current_screen = CurrentScreen::PasswordCheck;
+                            }
+                            std::io::ErrorKind::AlreadyExists => {
+                                // Like a bad username above, only the ID goes to the Join screen
+                                app.room_id = room.id;
+                                app.join_room_error = Some(JoinRoomError::UsernameTaken);
+                                app.join_room_input = JoinRoomInput::Username;
+                                app.current_screen = CurrentScreen::Join;
+                            }
+                            _ => {
+                                app.join_room_error = Some(JoinRoomError::RoomNotFound);
+                            }
+                        },
                     }
-                    KeyCode::Enter => {
-                        let select_room = app.room_lst[app.room_idx].clone();
-                        match Client::enter_room(
-                            app.username.clone(),
-                            select_room.0,
-                            Some(app.check_passwork.clone()),
-                            app.chat_room_record.clone(),
-                            app.chat_room_member.clone(),
-                            app.abandon.clone(),
-                            app.record_size,
-                            remote_server,
-                        )
-                        .await
-                        {
-                            Ok((msg_pipe, room_id)) => {
-                                app.room_name = select_room.1;
-                                app.msg_pipe = Some(msg_pipe);
-                                app.enter_room(room_id);
-                            }
-                            Err(e) => match e.kind() {
-                                std::io::ErrorKind::BrokenPipe => {
-                                    app.join_room_error = Some(JoinRoomError::RoomNotFound);
-                                }
-                                std::io::ErrorKind::InvalidInput => {
-                                    app.join_room_error = Some(JoinRoomError::WrongPassword);
+                }
+                CurrentScreen::PasswordCheck => {
+                    match (app.keymap.action(KeyContext::PasswordForm, &key), key.code) {
+                        (Some(Action::TogglePassword), _) => {
+                            app.show_password = !app.show_password;
+                        }
+                        (Some(Action::Back), _) => {
+                            if let Some(target) = app.password_target.take() {
+                                app.current_screen = target.back_to;
+                            }
+                            app.check_passwork.zeroize();
+                            app.show_password = false;
+                            app.join_room_error = None;
+                        }
+                        (None, KeyCode::Char(value)) => {
+                            app.check_passwork.push(value);
+                            app.join_room_error = None;
+                        }
+                        (None, KeyCode::Backspace) => {
+                            app.check_passwork.pop();
+                            app.join_room_error = None;
+                        }
+                        (Some(Action::Submit), _) => {
+                            let (target_id, target_name, invite_code) = match &app.password_target {
+                                Some(target) => (
+                                    target.room_id.clone(),
+                                    target.room_name.clone(),
+                                    target.invite_code.clone(),
+                                ),
+                                None => continue,
+                            };
+                            match Client::enter_room(
+                                app.username.clone(),
+                                target_id,
+                                invite_code.clone(),
+                                Some(app.check_passwork.clone()),
+                                app.room.clone(),
+                                app.record_size,
+                                remote_server,
+                            )
+                            .await
+                            {
+                                Ok((msg_pipe, joined_room)) => {
+                                    app.room_name = joined_room.room_name.unwrap_or(target_name);
+                                    app.msg_pipe = Some(msg_pipe);
+                                    app.enter_room(joined_room.room_id, remote_server, invite_code);
                                 }
-                                _ => {}
-                            },
+                                Err(e) => match e.kind() {
+                                    std::io::ErrorKind::BrokenPipe => {
+                                        app.join_room_error = Some(JoinRoomError::RoomNotFound);
+                                    }
+                                    std::io::ErrorKind::InvalidInput => {
+                                        app.join_room_error = Some(JoinRoomError::WrongPassword);
+                                    }
+                                    std::io::ErrorKind::PermissionDenied => {
+                                        app.join_room_error = Some(JoinRoomError::PasswordRequired);
+                                    }
+                                    std::io::ErrorKind::AlreadyExists => {
+                                        app.check_passwork.zeroize();
+                                        // The Join screen still holds a typed ID, a recent room
+                                        // only leaves its ID there
+                                        if let Some(target) = app.password_target.take() {
+                                            if let CurrentScreen::RecentRooms = target.back_to {
+                                                app.room_id = target.room_id;
+                                            }
+                                        }
+                                        app.join_room_error = Some(JoinRoomError::UsernameTaken);
+                                        app.current_screen = CurrentScreen::Join;
+                                    }
+                                    _ => {
+                                        app.join_room_error = Some(JoinRoomError::RoomNotFound);
+                                    }
+                                },
+                            }
                         }
+                        _ => {}
                     }
-                    _ => {}
-                },
+                }
                 CurrentScreen::Chat => {
                     if app.exiting {
-                        match key.code {
-                            KeyCode::Char('n') => app.exiting = false,
-                            KeyCode::Char('y') => {
+                        match app.keymap.action(KeyContext::ExitConfirm, &key) {
+                            Some(Action::Back) => app.exiting = false,
+                            Some(Action::Confirm) => {
                                 app.msg_pipe = None;
                                 // app.room_id = String::new();
                                 // app.current_screen = CurrentScreen::Entry;
@@ -447,33 +951,328 @@ async fn run_app<B: Backend>(
                         }
                     } else {
                         match app.chat_room_mode {
-                            ChatRoomMode::Normal => match key.code {
-                                KeyCode::Char('i') => {
-                                    app.chat_room_mode = ChatRoomMode::Input;
+                            ChatRoomMode::Normal => {
+                                match app.keymap.action(KeyContext::ChatNormal, &key) {
+                                    Some(Action::EnterInput) => {
+                                        app.chat_room_mode = ChatRoomMode::Input;
+                                    }
+                                    Some(Action::LeaveRoom) => {
+                                        app.exiting = true;
+                                    }
+                                    Some(Action::FocusMembers) => {
+                                        app.chat_room_mode = ChatRoomMode::MemberList;
+                                    }
+                                    _ => {}
                                 }
-                                KeyCode::Char('q') => {
-                                    app.exiting = true;
-                                }
-                                _ => {}
-                            },
-                            ChatRoomMode::Input => match key.code {
-                                KeyCode::Backspace => {
-                                    app.input.pop();
-                                }
-                                KeyCode::Char(value) => {
-                                    app.input.push(value);
+                            }
+                            ChatRoomMode::MemberList => {
+                                let chat_room_member =
+                                    app.room.chat_room_member.lock().await.clone();
+                                app.member_idx =
+                                    app.member_idx.min(chat_room_member.len().saturating_sub(1));
+                                let selected = chat_room_member.get(app.member_idx);
+
+                                match app.keymap.action(KeyContext::MemberList, &key) {
+                                    Some(Action::Back) => {
+                                        app.chat_room_mode = ChatRoomMode::Normal;
+                                    }
+                                    Some(Action::Up) => {
+                                        app.member_idx = app.member_idx.saturating_sub(1);
+                                    }
+                                    Some(Action::Down)
+                                        if chat_room_member.len() > app.member_idx + 1 =>
+                                    {
+                                        app.member_idx += 1;
+                                    }
+                                    Some(Action::PrivateMessage) => {
+                                        if let Some(member) = selected {
+                                            if member.name != app.username {
+                                                app.input = format!("/msg {} ", member.name);
+                                                app.chat_room_mode = ChatRoomMode::Input;
+                                            }
+                                        }
+                                    }
+                                    Some(Action::Mention) => {
+                                        if let Some(member) = selected {
+                                            app.input.push_str(&format!("@{} ", member.name));
+                                            app.chat_room_mode = ChatRoomMode::Input;
+                                        }
+                                    }
+                                    Some(Action::ToggleIgnore) => {
+                                        if let Some(member) = selected {
+                                            let ignored = app
+                                                .room
+                                                .ignored_users
+                                                .lock()
+                                                .await
+                                                .contains(&member.name);
+                                            if ignored || member.name != app.username {
+                                                if let Err(e) = app
+                                                    .set_ignored(
+                                                        remote_server,
+                                                        &member.name,
+                                                        !ignored,
+                                                    )
+                                                    .await
+                                                {
+                                                    warn!(error = %e, "failed to save the ignore list");
+                                                    app.push_local_msg(format!(
+                                                        "Failed to save the ignore list: {}",
+                                                        e
+                                                    ))
+                                                    .await;
+                                                }
+                                            }
+                                        }
+                                    }
+                                    Some(Action::Kick) => {
+                                        if let Some(member) = selected {
+                                            if member.name != app.username && app.is_owner().await {
+                                                // Gone once the room has been left
+                                                if let Some(pipe) = &app.msg_pipe {
+                                                    let _ = pipe
+                                                        .send(Client::moderation_frame(
+                                                            ModAction::Kick,
+                                                            &member.name,
+                                                        ))
+                                                        .await;
+                                                }
+                                            }
+                                        }
+                                    }
+                                    _ => {}
                                 }
-                                KeyCode::Enter => {
-                                    let a = app.msg_pipe.as_ref().unwrap();
-                                    let _ = a.send(app.input.clone()).await;
-                                    app.input.clear();
+                            }
+                            ChatRoomMode::Input => {
+                                let action = app.keymap.action(KeyContext::ChatInput, &key);
+                                if action != Some(Action::Complete) {
+                                    app.completion = None;
                                 }
-                                KeyCode::Esc => {
-                                    app.input.clear();
-                                    app.chat_room_mode = ChatRoomMode::Normal;
+                                match (action, key.code) {
+                                    (Some(Action::Complete), _) => {
+                                        match app.completion.as_mut() {
+                                            Some(completion) => {
+                                                completion.idx = (completion.idx + 1)
+                                                    % completion.candidates.len();
+                                            }
+                                            None => {
+                                                let members = app
+                                                    .room
+                                                    .chat_room_member
+                                                    .lock()
+                                                    .await
+                                                    .iter()
+                                                    .map(|x| x.name.clone())
+                                                    .collect::<Vec<String>>();
+                                                if let Some((start, candidates)) =
+                                                    completion_candidates(&app.input, &members)
+                                                {
+                                                    app.completion = Some(Completion {
+                                                        start,
+                                                        candidates,
+                                                        idx: 0,
+                                                    });
+                                                }
+                                            }
+                                        }
+                                        if let Some(completion) = &app.completion {
+                                            app.input.truncate(completion.start);
+                                            app.input
+                                                .push_str(&completion.candidates[completion.idx]);
+                                        }
+                                    }
+                                    (None, KeyCode::Backspace) => {
+                                        app.input.pop();
+                                    }
+                                    (None, KeyCode::Char(value)) => {
+                                        app.input.push(value);
+                                    }
+                                    (Some(Action::Submit), _) => {
+                                        // Jump back to the newest messages to see what was sent
+                                        app.chat_scroll = 0;
+                                        let a = app.msg_pipe.as_ref().unwrap();
+                                        let command = parse_chat_command(&app.input);
+                                        // Muted members can still use the commands that stay local
+                                        let sends_frame = match &command {
+                                            None => true,
+                                            Some(Ok(command)) => !command.is_local(),
+                                            Some(Err(_)) => false,
+                                        };
+                                        let muted = sends_frame && app.is_muted().await;
+                                        match command {
+                                            _ if muted => {
+                                                app.push_local_msg(
+                                                    "You are muted in this room".to_string(),
+                                                )
+                                                .await;
+                                                // The input may be a /password command
+                                                app.input.zeroize();
+                                            }
+                                            None => {
+                                                let _ = a.send(app.input.clone()).await;
+                                            }
+                                            Some(Ok(ChatCommand::PrivateMsg { target, text })) => {
+                                                let is_member = app
+                                                    .room
+                                                    .chat_room_member
+                                                    .lock()
+                                                    .await
+                                                    .iter()
+                                                    .any(|x| x.name == target);
+                                                if target == app.username {
+                                                    app.push_local_msg(
+                                                    "You cannot send a private message to yourself"
+                                                        .to_string(),
+                                                )
+                                                .await;
+                                                } else if !is_member {
+                                                    app.push_local_msg(format!(
+                                                        "{} is not in this room",
+                                                        target
+                                                    ))
+                                                    .await;
+                                                } else {
+                                                    let _ = a
+                                                        .send(Client::private_msg_frame(
+                                                            &target, &text,
+                                                        ))
+                                                        .await;
+                                                    app.push_local_msg(format!(
+                                                        "[DM to {}] {}",
+                                                        target, text
+                                                    ))
+                                                    .await;
+                                                }
+                                            }
+                                            Some(Ok(ChatCommand::Topic { topic })) => {
+                                                let _ = a.send(Client::topic_frame(&topic)).await;
+                                            }
+                                            Some(Ok(ChatCommand::Password { mut password })) => {
+                                                let is_valid = password.as_ref().is_none_or(|x| {
+                                                    x.len() >= 4
+                                                        && x.len() <= 20
+                                                        && !has_whitespace(x)
+                                                });
+                                                if !app.is_owner().await {
+                                                    app.push_local_msg(
+                                                        "Only the room owner can use /password"
+                                                            .to_string(),
+                                                    )
+                                                    .await;
+                                                } else if !is_valid {
+                                                    app.push_local_msg("Length of password should be between 4 and 20 (whitespace not allowed)".to_string()).await;
+                                                } else {
+                                                    // The room list is fetched again on every
+                                                    // visit, so its 🔑 shows what the server reports
+                                                    let _ = a
+                                                        .send(Client::password_frame(
+                                                            password.as_deref(),
+                                                        ))
+                                                        .await;
+                                                }
+                                                if let Some(password) = password.as_mut() {
+                                                    password.zeroize();
+                                                }
+                                                app.input.zeroize();
+                                            }
+                                            Some(Ok(ChatCommand::Ignore { target, ignored })) => {
+                                                let listed = app
+                                                    .room
+                                                    .ignored_users
+                                                    .lock()
+                                                    .await
+                                                    .contains(&target);
+                                                if target == app.username {
+                                                    app.push_local_msg(
+                                                        "You cannot ignore yourself".to_string(),
+                                                    )
+                                                    .await;
+                                                } else if listed == ignored {
+                                                    app.push_local_msg(if ignored {
+                                                        format!("{} is already ignored", target)
+                                                    } else {
+                                                        format!("{} is not ignored", target)
+                                                    })
+                                                    .await;
+                                                } else if let Err(e) = app
+                                                    .set_ignored(remote_server, &target, ignored)
+                                                    .await
+                                                {
+                                                    warn!(error = %e, "failed to save the ignore list");
+                                                    app.push_local_msg(format!(
+                                                        "Failed to save the ignore list: {}",
+                                                        e
+                                                    ))
+                                                    .await;
+                                                } else {
+                                                    app.push_local_msg(if ignored {
+                                                        format!("Ignoring {}", target)
+                                                    } else {
+                                                        format!("No longer ignoring {}", target)
+                                                    })
+                                                    .await;
+                                                }
+                                            }
+                                            Some(Ok(ChatCommand::ListIgnored)) => {
+                                                let ignored_users =
+                                                    app.room.ignored_users.lock().await.clone();
+                                                let msg = if ignored_users.is_empty() {
+                                                    "You are not ignoring anyone".to_string()
+                                                } else {
+                                                    format!(
+                                                        "Ignored users: {}",
+                                                        ignored_users.join(", ")
+                                                    )
+                                                };
+                                                app.push_local_msg(msg).await;
+                                            }
+                                            Some(Ok(ChatCommand::Moderate { action, target })) => {
+                                                let is_member = app
+                                                    .room
+                                                    .chat_room_member
+                                                    .lock()
+                                                    .await
+                                                    .iter()
+                                                    .any(|x| x.name == target);
+                                                if !app.is_owner().await {
+                                                    app.push_local_msg(format!(
+                                                        "Only the room owner can use /{}",
+                                                        action.as_str()
+                                                    ))
+                                                    .await;
+                                                } else if target == app.username {
+                                                    app.push_local_msg(format!(
+                                                        "You cannot use /{} on yourself",
+                                                        action.as_str()
+                                                    ))
+                                                    .await;
+                                                } else if !is_member {
+                                                    app.push_local_msg(format!(
+                                                        "{} is not in this room",
+                                                        target
+                                                    ))
+                                                    .await;
+                                                } else {
+                                                    let _ = a
+                                                        .send(Client::moderation_frame(
+                                                            action, &target,
+                                                        ))
+                                                        .await;
+                                                }
+                                            }
+                                            Some(Err(e)) => {
+                                                app.push_local_msg(e.message()).await;
+                                            }
+                                        }
+                                        app.input.clear();
+                                    }
+                                    (Some(Action::Back), _) => {
+                                        app.input.clear();
+                                        app.chat_room_mode = ChatRoomMode::Normal;
+                                    }
+                                    _ => {}
                                 }
-                                _ => {}
-                            },
+                            }
                         }
                     }
                 }
@@ -484,22 +1283,219 @@ async fn run_app<B: Backend>(
     Ok(())
 }
 
-fn is_valid_string(s: &str) -> bool {
-    static USERNAME_RESTRICT: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r#"([!@#$%\^\&\*\(\)\+=\[\]\{\}:;'"/<>|\\`~\?,\.\s]+)"#).unwrap());
+// Restores the terminal when main returns, early or not
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+// Errors are ignored, this runs while already exiting and there is nowhere to report them
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(
+        std::io::stderr(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        Show
+    );
+}
+
+// Written next to state.toml, or to the temp directory when there is no data directory
+fn write_crash_log(report: &str) -> Option<PathBuf> {
+    let dir = dirs::data_dir()
+        .map(|dir| dir.join("chat_client"))
+        .unwrap_or_else(std::env::temp_dir);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    let path = dir.join(format!("crash-{}.log", now));
+    std::fs::create_dir_all(&dir).ok()?;
+    std::fs::write(&path, report).ok()?;
+    Some(path)
+}
+
+// Applies what a mouse event can do on its own and returns the key press for the rest
+fn mouse_to_key(app: &mut App, mouse: MouseEvent, record_len: usize) -> Option<KeyEvent> {
+    let position = Position::new(mouse.column, mouse.row);
+    let clicked = |areas: &[(Rect, usize)]| {
+        areas
+            .iter()
+            .find(|(area, _)| area.contains(position))
+            .map(|(_, idx)| *idx)
+    };
+    let in_area = |area: Option<Rect>| area.is_some_and(|area| area.contains(position));
+    let left_click = mouse.kind == MouseEventKind::Down(MouseButton::Left);
+
+    if app.show_help {
+        return match mouse.kind {
+            MouseEventKind::ScrollUp => app.keymap.key_event(KeyContext::HelpOverlay, Action::Up),
+            MouseEventKind::ScrollDown => {
+                app.keymap.key_event(KeyContext::HelpOverlay, Action::Down)
+            }
+            _ => None,
+        };
+    }
+
+    match app.current_screen {
+        CurrentScreen::Entry if left_click => {
+            let (_, selection) = app
+                .click_areas
+                .entry_options
+                .iter()
+                .find(|(area, _)| area.contains(position))?;
+            app.current_selection = *selection;
+            app.keymap.key_event(KeyContext::Entry, Action::Select)
+        }
+        // The first click selects a room and a second one joins it
+        CurrentScreen::RoomSelect if left_click => {
+            let idx = clicked(&app.click_areas.rooms)?;
+            app.room_filter_editing = false;
+            if idx == app.room_idx {
+                app.keymap.key_event(KeyContext::RoomList, Action::Select)
+            } else {
+                app.room_idx = idx;
+                None
+            }
+        }
+        CurrentScreen::RecentRooms if left_click => {
+            let idx = clicked(&app.click_areas.recent_rooms)?;
+            if idx == app.recent_idx {
+                app.keymap
+                    .key_event(KeyContext::RecentRooms, Action::Select)
+            } else {
+                app.recent_idx = idx;
+                None
+            }
+        }
+        CurrentScreen::Chat if !app.exiting => match mouse.kind {
+            MouseEventKind::ScrollUp if in_area(app.click_areas.chat_history) => {
+                app.chat_scroll = (app.chat_scroll + 1).min(record_len.saturating_sub(1));
+                None
+            }
+            MouseEventKind::ScrollDown if in_area(app.click_areas.chat_history) => {
+                app.chat_scroll = app.chat_scroll.saturating_sub(1);
+                None
+            }
+            _ if !left_click => None,
+            _ if in_area(app.click_areas.chat_input) => match app.chat_room_mode {
+                ChatRoomMode::Input => None,
+                ChatRoomMode::Normal => app
+                    .keymap
+                    .key_event(KeyContext::ChatNormal, Action::EnterInput),
+                ChatRoomMode::MemberList => {
+                    app.chat_room_mode = ChatRoomMode::Normal;
+                    app.keymap
+                        .key_event(KeyContext::ChatNormal, Action::EnterInput)
+                }
+            },
+            _ => {
+                app.member_idx = clicked(&app.click_areas.members)?;
+                app.chat_room_mode = ChatRoomMode::MemberList;
+                None
+            }
+        },
+        _ => None,
+    }
+}
 
-    !USERNAME_RESTRICT.is_match(s)
+// Rings the terminal bell on mentions and highlight matches and asks the terminal for a
+// desktop notification (OSC 777 and OSC 9), each unless turned off in the config
+fn notify_mention<W: Write>(
+    writer: &mut W,
+    room_name: &str,
+    settings: &NotificationConfig,
+) -> std::io::Result<()> {
+    if settings.bell {
+        write!(writer, "\x07")?;
+    }
+    if settings.desktop {
+        let body = format!("New mention or highlight in {}", room_name);
+        write!(
+            writer,
+            "\x1b]777;notify;Chat room;{}\x07\x1b]9;{}\x07",
+            body, body
+        )?;
+    }
+    writer.flush()
 }
 
-fn is_valid_string_with_whitespace(s: &str) -> bool {
-    static USERNAME_RESTRICT: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r#"([@#$%\^\&\(\)\+=\[\]\{\}:;'"/|\\`~,\.]+)"#).unwrap());
+// Accepts a bare room ID, "room_id?invite=code" or a "chat://host/room_id?invite=code" link.
+// A link made on another server is refused, its invite code means nothing to this one.
+fn parse_room_target(
+    s: &str,
+    remote_server: &str,
+) -> Result<(String, Option<String>), JoinRoomError> {
+    let target = match s.strip_prefix("chat://") {
+        Some(link) => {
+            let (host, target) = link
+                .split_once('/')
+                .ok_or(JoinRoomError::RoomIdLengthError)?;
+            if !host.eq_ignore_ascii_case(remote_server) {
+                return Err(JoinRoomError::OtherServer(host.to_string()));
+            }
+            target
+        }
+        None => s,
+    };
+    let (room_id, invite_code) = match target.split_once("?invite=") {
+        Some((room_id, invite_code)) => (room_id, Some(invite_code)),
+        None => (target, None),
+    };
 
-    !USERNAME_RESTRICT.is_match(s)
+    if !is_valid_room_id(room_id) || !invite_code.is_none_or(is_valid_invite_code) {
+        return Err(JoinRoomError::RoomIdLengthError);
+    }
+    Ok((room_id.to_string(), invite_code.map(|x| x.to_string())))
 }
 
-fn has_whitespace(s: &str) -> bool {
-    static USERNAME_RESTRICT: Lazy<Regex> = Lazy::new(|| Regex::new(r#"([\s]+)"#).unwrap());
+fn invite_link(remote_server: &str, room_id: &str, invite_code: &str) -> String {
+    format!(
+        "chat://{}/{}?invite={}",
+        remote_server, room_id, invite_code
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: &str = "chat.example.com:8080";
 
-    USERNAME_RESTRICT.is_match(s)
+    #[test]
+    fn parse_room_target_bare_id() {
+        assert!(matches!(
+            parse_room_target("a1b2-c3", SERVER),
+            Ok((id, None)) if id == "a1b2-c3"
+        ));
+    }
+
+    #[test]
+    fn parse_room_target_matching_link() {
+        let link = invite_link(SERVER, "a1b2-c3", "abc123");
+        assert!(matches!(
+            parse_room_target(&link, SERVER),
+            Ok((id, Some(code))) if id == "a1b2-c3" && code == "abc123"
+        ));
+    }
+
+    #[test]
+    fn parse_room_target_link_for_another_server() {
+        let link = invite_link("evil.example.com:8080", "a1b2-c3", "abc123");
+        assert!(matches!(
+            parse_room_target(&link, SERVER),
+            Err(JoinRoomError::OtherServer(host)) if host == "evil.example.com:8080"
+        ));
+    }
+
+    #[test]
+    fn parse_room_target_bad_invite_code() {
+        let link = invite_link(SERVER, "a1b2-c3", "not a code!");
+        assert!(matches!(
+            parse_room_target(&link, SERVER),
+            Err(JoinRoomError::RoomIdLengthError)
+        ));
+    }
 }
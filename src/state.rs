@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+const MAX_RECENT_ROOMS: usize = 9;
+
+// Remembered between launches, unlike Config this is written by the client rather than the user
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub last_username: Option<String>,
+    // Most recently joined first
+    pub recent_rooms: Vec<RecentRoom>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentRoom {
+    pub id: String,
+    pub name: String,
+    pub server: String,
+    // Private rooms can't be rejoined without it
+    pub invite_code: Option<String>,
+}
+
+impl State {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("chat_client").join("state.toml"))
+    }
+
+    // A missing or unreadable file just means starting with nothing remembered
+    pub fn load(path: &Path) -> State {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let content =
+            toml::to_string_pretty(self).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        fs::write(path, content)
+    }
+
+    pub fn remember_room(&mut self, room: RecentRoom) {
+        self.recent_rooms
+            .retain(|x| x.id != room.id || x.server != room.server);
+        self.recent_rooms.insert(0, room);
+        self.recent_rooms.truncate(MAX_RECENT_ROOMS);
+    }
+
+    pub fn forget_room(&mut self, id: &str, server: &str) {
+        self.recent_rooms
+            .retain(|x| x.id != id || x.server != server);
+    }
+
+    pub fn recent_rooms_on(&self, server: &str) -> Vec<&RecentRoom> {
+        self.recent_rooms
+            .iter()
+            .filter(|room| room.server == server)
+            .collect()
+    }
+}
@@ -0,0 +1,266 @@
+use crate::config::{StyleConfig, ThemeConfig};
+use crate::util::stable_hash;
+use ratatui::style::{Color, Modifier, Style};
+use std::str::FromStr;
+
+pub const PRESET_NAMES: &[&str] = &["dark", "light", "high-contrast"];
+
+// Every style the screens draw with, so a theme only has to be written once
+#[derive(Debug, Clone)]
+pub struct Theme {
+    // Selected option or input box on the forms
+    pub active: Style,
+    // Unselected option or input box on the forms
+    pub inactive: Style,
+    // Highlighted room, recent room or focused filter
+    pub focus: Style,
+    pub error: Style,
+    // Secondary text like room metadata and hidden message notes
+    pub dim: Style,
+    // Topic bar above the chat history
+    pub bar: Style,
+    // Border of the panel receiving keys in the chat room
+    pub input_focus: Style,
+    pub member: Style,
+    pub member_selected: Style,
+    pub own_msg: Style,
+    pub other_msg: Style,
+    pub system_msg: Style,
+    pub private_msg: Style,
+    pub mention_msg: Style,
+    pub highlight_msg: Style,
+    // Sender names are colored from this, no name colors when empty
+    pub name_palette: Vec<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Theme {
+            active: Style::default().bg(Color::LightYellow).fg(Color::Black),
+            inactive: Style::default().bg(Color::DarkGray).fg(Color::Black),
+            focus: Style::default().fg(Color::LightYellow),
+            error: Style::default().fg(Color::Red),
+            dim: Style::default().fg(Color::DarkGray),
+            bar: Style::default().bg(Color::DarkGray),
+            input_focus: Style::default().fg(Color::Blue),
+            member: Style::default().fg(Color::Green),
+            member_selected: Style::default().bg(Color::DarkGray),
+            own_msg: Style::default().fg(Color::LightYellow),
+            other_msg: Style::default().fg(Color::LightGreen),
+            system_msg: Style::default(),
+            private_msg: Style::default().fg(Color::LightMagenta),
+            mention_msg: Style::default()
+                .fg(Color::LightCyan)
+                .add_modifier(Modifier::BOLD),
+            highlight_msg: Style::default()
+                .fg(Color::LightRed)
+                .add_modifier(Modifier::BOLD),
+            // Light tones from the 256 color palette that stay readable on a dark background
+            name_palette: [114, 117, 180, 176, 150, 110, 216, 183, 79, 222]
+                .into_iter()
+                .map(Color::Indexed)
+                .collect(),
+        }
+    }
+
+    // Light colors wash out on a white background, so this sticks to the darker ones
+    pub fn light() -> Self {
+        Theme {
+            active: Style::default().bg(Color::Blue).fg(Color::White),
+            inactive: Style::default().bg(Color::Gray).fg(Color::Black),
+            focus: Style::default().fg(Color::Blue),
+            error: Style::default().fg(Color::Red),
+            dim: Style::default().fg(Color::DarkGray),
+            bar: Style::default().bg(Color::Gray).fg(Color::Black),
+            input_focus: Style::default().fg(Color::Blue),
+            member: Style::default().fg(Color::Green),
+            member_selected: Style::default().bg(Color::Gray),
+            own_msg: Style::default().fg(Color::Blue),
+            other_msg: Style::default().fg(Color::Green),
+            system_msg: Style::default(),
+            private_msg: Style::default().fg(Color::Magenta),
+            mention_msg: Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            highlight_msg: Style::default()
+                .fg(Color::Rgb(175, 95, 0))
+                .add_modifier(Modifier::BOLD),
+            name_palette: [22, 25, 94, 90, 30, 130, 57, 124]
+                .into_iter()
+                .map(Color::Indexed)
+                .collect(),
+        }
+    }
+
+    pub fn high_contrast() -> Self {
+        Theme {
+            active: Style::default()
+                .bg(Color::White)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            inactive: Style::default().bg(Color::Black).fg(Color::White),
+            focus: Style::default()
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD),
+            error: Style::default()
+                .fg(Color::LightRed)
+                .add_modifier(Modifier::BOLD),
+            dim: Style::default().fg(Color::Gray),
+            bar: Style::default().bg(Color::White).fg(Color::Black),
+            input_focus: Style::default()
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD),
+            member: Style::default().fg(Color::White),
+            member_selected: Style::default().add_modifier(Modifier::REVERSED),
+            own_msg: Style::default()
+                .fg(Color::LightYellow)
+                .add_modifier(Modifier::BOLD),
+            other_msg: Style::default().fg(Color::White),
+            system_msg: Style::default().fg(Color::Gray),
+            private_msg: Style::default()
+                .fg(Color::LightMagenta)
+                .add_modifier(Modifier::BOLD),
+            mention_msg: Style::default()
+                .bg(Color::LightCyan)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            highlight_msg: Style::default()
+                .bg(Color::LightYellow)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            name_palette: vec![
+                Color::LightGreen,
+                Color::LightCyan,
+                Color::LightMagenta,
+                Color::LightBlue,
+                Color::White,
+            ],
+        }
+    }
+
+    // Used when NO_COLOR is set, telling things apart with text attributes only
+    pub fn monochrome() -> Self {
+        Theme {
+            active: Style::default().add_modifier(Modifier::REVERSED),
+            inactive: Style::default(),
+            focus: Style::default().add_modifier(Modifier::BOLD),
+            error: Style::default().add_modifier(Modifier::BOLD),
+            dim: Style::default().add_modifier(Modifier::DIM),
+            bar: Style::default().add_modifier(Modifier::REVERSED),
+            input_focus: Style::default().add_modifier(Modifier::BOLD),
+            member: Style::default(),
+            member_selected: Style::default().add_modifier(Modifier::REVERSED),
+            own_msg: Style::default().add_modifier(Modifier::BOLD),
+            other_msg: Style::default(),
+            system_msg: Style::default().add_modifier(Modifier::DIM),
+            private_msg: Style::default().add_modifier(Modifier::ITALIC),
+            mention_msg: Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+            highlight_msg: Style::default().add_modifier(Modifier::UNDERLINED),
+            name_palette: vec![],
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Theme::dark()),
+            "light" => Some(Theme::light()),
+            "high-contrast" => Some(Theme::high_contrast()),
+            _ => None,
+        }
+    }
+
+    // Starts from the chosen preset and applies the styles set in the config on top.
+    // With NO_COLOR the base is monochrome, but colors written in the config still win.
+    pub fn from_config(config: &ThemeConfig, no_color: bool) -> Result<Self, String> {
+        let mut theme = match &config.preset {
+            Some(name) => Theme::preset(name).ok_or(format!(
+                "theme.preset: unknown preset {}, expected one of {}",
+                name,
+                PRESET_NAMES.join(", ")
+            ))?,
+            None => Theme::dark(),
+        };
+        if no_color {
+            theme = Theme::monochrome();
+        }
+
+        for (name, style_config) in config.styles.iter() {
+            let style = theme
+                .style_mut(name)
+                .ok_or(format!("theme.{}: unknown style", name))?;
+            *style = apply_style_config(*style, style_config)
+                .map_err(|e| format!("theme.{}: {}", name, e))?;
+        }
+        Ok(theme)
+    }
+
+    // The same name always gets the same color, on every launch
+    pub fn name_color(&self, name: &str) -> Option<Color> {
+        if self.name_palette.is_empty() {
+            return None;
+        }
+        let idx = stable_hash(name) as usize % self.name_palette.len();
+        Some(self.name_palette[idx])
+    }
+
+    fn style_mut(&mut self, name: &str) -> Option<&mut Style> {
+        match name {
+            "active" => Some(&mut self.active),
+            "inactive" => Some(&mut self.inactive),
+            "focus" => Some(&mut self.focus),
+            "error" => Some(&mut self.error),
+            "dim" => Some(&mut self.dim),
+            "bar" => Some(&mut self.bar),
+            "input_focus" => Some(&mut self.input_focus),
+            "member" => Some(&mut self.member),
+            "member_selected" => Some(&mut self.member_selected),
+            "own_msg" => Some(&mut self.own_msg),
+            "other_msg" => Some(&mut self.other_msg),
+            "system_msg" => Some(&mut self.system_msg),
+            "private_msg" => Some(&mut self.private_msg),
+            "mention_msg" => Some(&mut self.mention_msg),
+            "highlight_msg" => Some(&mut self.highlight_msg),
+            _ => None,
+        }
+    }
+}
+
+fn apply_style_config(mut style: Style, config: &StyleConfig) -> Result<Style, String> {
+    let parse =
+        |value: &str| Color::from_str(value).map_err(|_| format!("unknown color {}", value));
+    match config {
+        StyleConfig::Color(fg) => Ok(style.fg(parse(fg)?)),
+        StyleConfig::Full {
+            fg,
+            bg,
+            bold,
+            italic,
+            underlined,
+            reversed,
+        } => {
+            if let Some(fg) = fg {
+                style = style.fg(parse(fg)?);
+            }
+            if let Some(bg) = bg {
+                style = style.bg(parse(bg)?);
+            }
+            for (flag, modifier) in [
+                (bold, Modifier::BOLD),
+                (italic, Modifier::ITALIC),
+                (underlined, Modifier::UNDERLINED),
+                (reversed, Modifier::REVERSED),
+            ] {
+                style = match flag {
+                    Some(true) => style.add_modifier(modifier),
+                    Some(false) => style.remove_modifier(modifier),
+                    None => style,
+                };
+            }
+            Ok(style)
+        }
+    }
+}
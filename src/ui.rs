@@ -5,81 +5,144 @@ use ratatui::widgets::{Block, Borders, Clear, List, ListItem, Padding, Paragraph
 use ratatui::Frame;
 use regex::Regex;
 use std::collections::VecDeque;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use crate::app::*;
-use lazy_static::lazy_static;
-
-const ROOM_LIST_HEIGHT: usize = 4;
+use crate::chat_command::COMMAND_HELP;
+use crate::keymap::{Action, KeyContext};
+use crate::theme::Theme;
+use crate::util::mentions;
+
+const ROOM_LIST_HEIGHT: usize = 5;
+// Anything smaller gets a "terminal too small" screen instead
+const MIN_WIDTH: u16 = 40;
+const MIN_HEIGHT: u16 = 15;
+// Below this width the chat room's side panel no longer fits next to the chat
+const SIDEBAR_MIN_WIDTH: u16 = 80;
+// Narrow terminals at least this tall get the side panel under the chat
+const STACKED_MIN_HEIGHT: u16 = 30;
+const STACKED_PANEL_HEIGHT: u16 = 10;
+// Width of the member list on terminals too small to keep it open
+const COLLAPSED_PANEL_WIDTH: u16 = 28;
 
 enum MsgType {
     UserMsg,
     OtherMsg,
     SystemMsg,
-}
-
-lazy_static! {
-    static ref NORMAL_MODE_INSTRUCTION: Vec<&'static str> = {
-        let mut str_vec = vec![];
-        str_vec.push("'i' => switch to input mode");
-        str_vec.push("'q' => exit the room");
-        str_vec
-    };
-    static ref INPUT_MODE_INSTRUCTION: Vec<&'static str> = {
-        let mut str_vec = vec![];
-        str_vec.push("'Esc' => exit input mode");
-        str_vec
-    };
+    PrivateMsg,
+    MentionMsg,
+    HighlightMsg,
 }
 
 pub fn ui(
     frame: &mut Frame,
     app: &App,
-    chat_room_record: VecDeque<String>,
-    chat_room_member: Vec<String>,
-) {
+    mut chat_room_record: VecDeque<String>,
+    chat_room_member: Vec<Member>,
+    room_topic: String,
+    ignored_users: Vec<String>,
+    remote_server: &str,
+) -> ClickAreas {
+    let mut click_areas = ClickAreas::default();
+    let area = frame.area();
+    if area.width < MIN_WIDTH || area.height < MIN_HEIGHT {
+        let message = Paragraph::new(vec![
+            Line::styled("Terminal too small", app.theme.error),
+            Line::from(format!(
+                "{}x{}, need {}x{}",
+                area.width, area.height, MIN_WIDTH, MIN_HEIGHT
+            )),
+        ])
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: true });
+        frame.render_widget(
+            message,
+            centered_rect_with_constant_size(area.width, 2, area),
+        );
+        return click_areas;
+    }
+
     match app.current_screen {
         CurrentScreen::Entry => {
             let instruction_area = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Fill(1), Constraint::Length(3)])
+                .constraints([
+                    Constraint::Length(if app.entry_notice.is_some() { 3 } else { 0 }),
+                    Constraint::Fill(1),
+                    Constraint::Length(3),
+                ])
                 .split(frame.area());
+
+            if let Some(notice) = &app.entry_notice {
+                let notice_block = Block::default()
+                    .title("Press any key to dismiss")
+                    .borders(Borders::ALL)
+                    .border_style(app.theme.error);
+                let notice = Paragraph::new(notice.clone()).block(notice_block);
+                frame.render_widget(notice, instruction_area[0]);
+            }
             let instruction_block = Block::default()
                 .title("Instructions")
                 .borders(Borders::ALL)
                 .style(Style::default());
-            let instruction = Paragraph::new("Tab = switch selection, Enter = select, q = quit")
-                .block(instruction_block);
-            frame.render_widget(instruction, instruction_area[1]);
+            let keys = |action| app.keymap.keys(KeyContext::Entry, action);
+            let instruction = Paragraph::new(format!(
+                "{} = switch selection, {} = select, {} = quit, {} = help",
+                keys(Action::NextOption),
+                keys(Action::Select),
+                keys(Action::Quit),
+                keys(Action::Help)
+            ))
+            .block(instruction_block);
+            frame.render_widget(instruction, instruction_area[2]);
 
-            let popup_area = centered_rect_with_constant_size(26, 6, instruction_area[0]);
+            let popup_area = centered_rect_with_constant_size(26, 9, instruction_area[1]);
             let popup_chunks = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .constraints([
+                    Constraint::Fill(1),
+                    Constraint::Fill(1),
+                    Constraint::Fill(1),
+                ])
                 .split(popup_area);
+            click_areas.entry_options = vec![
+                (popup_chunks[0], CurrentSelection::Create),
+                (popup_chunks[1], CurrentSelection::Join),
+                (popup_chunks[2], CurrentSelection::Recent),
+            ];
 
             let mut create_block = Block::default()
                 .padding(Padding::horizontal(5))
                 .borders(Borders::ALL)
-                .style(Style::default().bg(Color::DarkGray));
+                .style(app.theme.inactive);
             let mut join_block = Block::default()
                 .padding(Padding::horizontal(6))
                 .borders(Borders::ALL)
-                .style(Style::default().bg(Color::DarkGray));
+                .style(app.theme.inactive);
+            let mut recent_block = Block::default()
+                .padding(Padding::horizontal(6))
+                .borders(Borders::ALL)
+                .style(app.theme.inactive);
 
-            let active_style = Style::default().bg(Color::LightYellow).fg(Color::Black);
+            let active_style = app.theme.active;
 
             match app.current_selection {
                 CurrentSelection::Create => create_block = create_block.style(active_style),
                 CurrentSelection::Join => join_block = join_block.style(active_style),
+                CurrentSelection::Recent => recent_block = recent_block.style(active_style),
             }
 
-            let create_text = Text::styled("Create a room", Style::default().fg(Color::Black));
-            let join_text = Text::styled("Join a room", Style::default().fg(Color::Black));
+            let create_text = Text::raw("Create a room");
+            let join_text = Text::raw("Join a room");
             let create_option = Paragraph::new(create_text).block(create_block);
             frame.render_widget(create_option, popup_chunks[0]);
 
             let join_option = Paragraph::new(join_text).block(join_block);
             frame.render_widget(join_option, popup_chunks[1]);
+
+            let recent_text = Text::raw("Recent rooms");
+            let recent_option = Paragraph::new(recent_text).block(recent_block);
+            frame.render_widget(recent_option, popup_chunks[2]);
         }
         CurrentScreen::Create => {
             let area_chunks = Layout::default()
@@ -108,13 +171,16 @@ pub fn ui(
                         CreateRoomError::InvalidRoomNameLength => {
                             "Length of room name should be between 1 and 100".to_string()
                         }
+                        CreateRoomError::UsernameTaken => {
+                            "This username is already taken, please choose another one".to_string()
+                        }
                         _ => {
                             "".to_string()
                         }
                     };
                     let error_block = Block::default()
                         .borders(Borders::ALL)
-                        .border_style(Style::default().fg(Color::Red));
+                        .border_style(app.theme.error);
 
                     let error = Paragraph::new(error_msg).block(error_block);
                     frame.render_widget(error, area_chunks[0])
@@ -126,9 +192,14 @@ pub fn ui(
                 .title("Instructions")
                 .borders(Borders::ALL)
                 .style(Style::default());
-            let instruction =
-                Paragraph::new("Tab = switch input, Enter = confirm username and room name, Esc = back to main menu")
-                    .block(instruction_block);
+            let keys = |action| app.keymap.keys(KeyContext::Form, action);
+            let instruction = Paragraph::new(format!(
+                "{} = switch input, {} = confirm username and room name, {} = back to main menu",
+                keys(Action::NextField),
+                keys(Action::Submit),
+                keys(Action::Back)
+            ))
+            .block(instruction_block);
 
             frame.render_widget(instruction, area_chunks[2]);
 
@@ -141,14 +212,14 @@ pub fn ui(
             let mut username_block = Block::default()
                 .title("Please enter a uername")
                 .borders(Borders::ALL)
-                .style(Style::default().bg(Color::DarkGray).fg(Color::Black));
+                .style(app.theme.inactive);
 
             let mut room_name_block = Block::default()
                 .title("Please enter a room name")
                 .borders(Borders::ALL)
-                .style(Style::default().bg(Color::DarkGray).fg(Color::Black));
+                .style(app.theme.inactive);
 
-            let active_style = Style::default().bg(Color::LightYellow).fg(Color::Black);
+            let active_style = app.theme.active;
 
             match app.create_room_input {
                 CreateRoomInput::Username => {
@@ -166,18 +237,23 @@ pub fn ui(
             frame.render_widget(room_name, popup_chunks[1]);
 
             if app.password_prompt {
-                let prompt_layout = centered_rect_with_constant_size(80, 5, frame.area());
-                frame.render_widget(Clear, prompt_layout);
-                let pupup_dialog_block = Block::default()
-                    .padding(Padding::vertical(1))
-                    .title("Press 'y' to set a password, or press 'n' to create a room without password")
-                    .title_alignment(Alignment::Center)
-                    .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::LightYellow));
-                let popup_dialog = Paragraph::new("Do you want to set a password for this room?")
-                    .alignment(Alignment::Center)
-                    .block(pupup_dialog_block);
-                frame.render_widget(popup_dialog, prompt_layout);
+                render_dialog(
+                    frame,
+                    format!(
+                        "Press {} to set a password, or press {} to create a room without password",
+                        app.keymap.keys(KeyContext::CreatePrompt, Action::SetPassword),
+                        app.keymap.keys(KeyContext::CreatePrompt, Action::NoPassword)
+                    ),
+                    vec![
+                        "Do you want to set a password for this room?".to_string(),
+                        format!(
+                            "Private room (hidden from the room list, invite only): {} - press {} to toggle",
+                            if app.private_room { "on" } else { "off" },
+                            app.keymap.keys(KeyContext::CreatePrompt, Action::TogglePrivate)
+                        ),
+                    ],
+                    app.theme.focus,
+                );
             }
         }
         CurrentScreen::CreatePassword => {
@@ -190,16 +266,23 @@ pub fn ui(
                 ])
                 .split(frame.area());
             let instructions_block = Block::default().borders(Borders::ALL).title("Instructions");
-            let instructions = Paragraph::new("Enter = confirm, Esc = back to last page")
-                .block(instructions_block);
+            let keys = |action| app.keymap.keys(KeyContext::PasswordForm, action);
+            let instructions = Paragraph::new(format!(
+                "{} = confirm, {} = show/hide password, {} = back to last page",
+                keys(Action::Submit),
+                keys(Action::TogglePassword),
+                keys(Action::Back)
+            ))
+            .block(instructions_block);
             frame.render_widget(instructions, area_chunks[2]);
 
             let password_area = centered_rect_with_constant_size(40, 3, area_chunks[1]);
             let password_block = Block::default()
                 .borders(Borders::ALL)
-                .style(Style::default().bg(Color::LightYellow).fg(Color::Black))
+                .style(app.theme.active)
                 .title("Please enter a password for this room");
-            let password = Paragraph::new(app.password.clone()).block(password_block);
+            let password = Paragraph::new(mask_password(&app.password, app.show_password))
+                .block(password_block);
 
             frame.render_widget(password, password_area);
 
@@ -207,7 +290,7 @@ pub fn ui(
                 Some(error) => {
                     let err_block = Block::default()
                         .borders(Borders::ALL)
-                        .border_style(Style::default().fg(Color::Red));
+                        .border_style(app.theme.error);
                     match error {
                         CreateRoomError::ServerError => {
                             let err = Paragraph::new("Server Error").block(err_block);
@@ -233,33 +316,24 @@ pub fn ui(
                 ])
                 .split(frame.area());
             let instruction_block = Block::default().title("Instructions").borders(Borders::ALL);
-            let instruction = Paragraph::new(
-                "Tab = switch input, Enter = confirm your input, Esc = back to main menu",
-            )
+            let keys = |action| app.keymap.keys(KeyContext::Form, action);
+            let instruction = Paragraph::new(format!(
+                "{} = switch input, {} = join by room ID or browse rooms, {} = back to main menu",
+                keys(Action::NextField),
+                keys(Action::Submit),
+                keys(Action::Back)
+            ))
             .block(instruction_block);
 
             frame.render_widget(instruction, area_chunks[2]);
 
             match &app.join_room_error {
                 Some(error) => {
-                    let error_msg = match error {
-                        JoinRoomError::InvalidUsername => {
-                            "Username should not contain special characters or whitespace"
-                                .to_string()
-                        }
-                        JoinRoomError::RoomIdLengthError => "Invalid Room Id format".to_string(),
-                        JoinRoomError::RoomNotFound => "Room not found".to_string(),
-                        JoinRoomError::InvalidUsernameLength => {
-                            "Username's length should be between 1 and 50".to_string()
-                        }
-                        JoinRoomError::GetRoomListFailed => "Failed to get room list".to_string(),
-                        JoinRoomError::WrongPassword => "Wrong password".to_string(),
-                        JoinRoomError::ZeroRooms => "There is no room to join".to_string(),
-                    };
+                    let error_msg = join_room_error_msg(error);
 
                     let error_block = Block::default()
                         .borders(Borders::ALL)
-                        .border_style(Style::default().fg(Color::Red));
+                        .border_style(app.theme.error);
 
                     let error = Paragraph::new(error_msg).block(error_block);
 
@@ -268,43 +342,120 @@ pub fn ui(
                 None => {}
             }
 
-            let popup_area = centered_rect_with_constant_size(40, 3, area_chunks[1]);
-            //let popup_chunks = Layout::default()
-            //    .direction(Direction::Vertical)
-            //    .constraints([Constraint::Fill(1), Constraint::Fill(1)])
-            //    .split(popup_area);
+            let popup_area = centered_rect_with_constant_size(40, 6, area_chunks[1]);
+            let popup_chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Fill(1), Constraint::Fill(1)])
+                .split(popup_area);
 
-            //let active_style = Style::default().bg(Color::LightYellow).fg(Color::Black);
+            let active_style = app.theme.active;
 
-            let username_input_block = Block::default()
+            let mut username_input_block = Block::default()
                 .title("Please enter a uername")
                 .borders(Borders::ALL)
-                .style(Style::default().bg(Color::LightYellow).fg(Color::Black));
-
-            //let mut room_id_input_block = Block::default()
-            //    .title("Please enter the room ID")
-            //    .borders(Borders::ALL)
-            //    .style(Style::default().bg(Color::DarkGray).fg(Color::Black));
-
-            //match app.join_room_input {
-            //    JoinRoomInput::Username => {
-            //        username_input_block = username_input_block.style(active_style)
-            //    }
-            //    JoinRoomInput::RoomId => {
-            //        room_id_input_block = room_id_input_block.style(active_style)
-            //    }
-            //};
+                .style(app.theme.inactive);
+
+            let mut room_id_input_block = Block::default()
+                .title("Room ID / invite link (empty = browse)")
+                .borders(Borders::ALL)
+                .style(app.theme.inactive);
+
+            match app.join_room_input {
+                JoinRoomInput::Username => {
+                    username_input_block = username_input_block.style(active_style)
+                }
+                JoinRoomInput::RoomId => {
+                    room_id_input_block = room_id_input_block.style(active_style)
+                }
+            };
 
             let username = Paragraph::new(app.username.clone()).block(username_input_block);
-            //let room_id = Paragraph::new(app.room_id.clone()).block(room_id_input_block);
+            let room_id = Paragraph::new(app.room_id.clone()).block(room_id_input_block);
+
+            frame.render_widget(username, popup_chunks[0]);
+            frame.render_widget(room_id, popup_chunks[1])
+        }
+        CurrentScreen::RecentRooms => {
+            let area_chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Fill(1),
+                    Constraint::Length(3),
+                ])
+                .split(frame.area());
 
-            frame.render_widget(username, popup_area);
-            //frame.render_widget(room_id, popup_chunks[1])
+            let instruction_block = Block::default().title("Instructions").borders(Borders::ALL);
+            let keys = |action| app.keymap.keys(KeyContext::RecentRooms, action);
+            let instruction = Paragraph::new(format!(
+                "1-9 = rejoin that room, {}/{} + {} = rejoin the selected room, {} = forget, {} = back to main menu",
+                keys(Action::Up),
+                keys(Action::Down),
+                keys(Action::Select),
+                keys(Action::Forget),
+                keys(Action::Back)
+            ))
+            .block(instruction_block)
+            .wrap(Wrap { trim: true });
+            frame.render_widget(instruction, area_chunks[2]);
+
+            if let Some(error) = &app.join_room_error {
+                let error_block = Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(app.theme.error);
+                let error = Paragraph::new(join_room_error_msg(error)).block(error_block);
+                frame.render_widget(error, area_chunks[0]);
+            }
+
+            let recent_rooms = app.state.recent_rooms_on(remote_server);
+            let rooms_block = Block::default()
+                .title(format!("Recent rooms (joining as {})", app.username))
+                .borders(Borders::ALL);
+            if recent_rooms.is_empty() {
+                let empty = Paragraph::new("No recent rooms yet, rooms you join will show up here")
+                    .block(rooms_block);
+                frame.render_widget(empty, area_chunks[1]);
+            } else {
+                let inner_area = rooms_block.inner(area_chunks[1]);
+                for idx in 0..recent_rooms.len().min(inner_area.height as usize) {
+                    let row =
+                        Rect::new(inner_area.x, inner_area.y + idx as u16, inner_area.width, 1);
+                    click_areas.recent_rooms.push((row, idx));
+                }
+                let items = recent_rooms
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, room)| {
+                        let line = format!(
+                            "{}. {} ({}){}",
+                            idx + 1,
+                            if room.name.is_empty() {
+                                "Unnamed room"
+                            } else {
+                                &room.name
+                            },
+                            room.id,
+                            if room.invite_code.is_some() {
+                                " private"
+                            } else {
+                                ""
+                            }
+                        );
+                        if idx == app.recent_idx {
+                            ListItem::new(line).style(app.theme.focus)
+                        } else {
+                            ListItem::new(line)
+                        }
+                    })
+                    .collect::<Vec<ListItem>>();
+                frame.render_widget(List::new(items).block(rooms_block), area_chunks[1]);
+            }
         }
         CurrentScreen::RoomSelect => {
             let area_chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints([
+                    Constraint::Length(3),
                     Constraint::Length(3),
                     Constraint::Fill(1),
                     Constraint::Length(1),
@@ -313,21 +464,70 @@ pub fn ui(
                 .split(frame.area());
 
             let instruction_block = Block::default().title("Instructions").borders(Borders::ALL);
-            let instructions = Paragraph::new("Enter = select, 'r' = reload, ArrowUp = prev room, ArrowDown = next room, Esc = back to set username").block(instruction_block);
-            frame.render_widget(instructions, area_chunks[3]);
+            let instructions = if app.room_filter_editing {
+                Paragraph::new(format!(
+                    "Type to filter by room name or ID, {} = apply filter, {} = clear filter",
+                    app.keymap.keys(KeyContext::Form, Action::Submit),
+                    app.keymap.keys(KeyContext::Form, Action::Back)
+                ))
+                .block(instruction_block)
+            } else {
+                let keys = |action| app.keymap.keys(KeyContext::RoomList, action);
+                Paragraph::new(format!(
+                    "{} = select, {} = reload, {} = prev room, {} = next room, {} = filter, {} = open rooms only, {} = password rooms only, {} = change sort, {} = back to set username",
+                    keys(Action::Select),
+                    keys(Action::ReloadRooms),
+                    keys(Action::Up),
+                    keys(Action::Down),
+                    keys(Action::Filter),
+                    keys(Action::OpenOnly),
+                    keys(Action::PasswordOnly),
+                    keys(Action::CycleSort),
+                    keys(Action::Back)
+                ))
+                .block(instruction_block)
+            };
+            frame.render_widget(instructions.wrap(Wrap { trim: true }), area_chunks[4]);
+
+            let kind_filter = match app.room_kind_filter {
+                RoomKindFilter::All => "all rooms",
+                RoomKindFilter::OpenOnly => "open rooms only",
+                RoomKindFilter::PasswordOnly => "password rooms only",
+            };
+            let sort = match app.room_sort {
+                RoomSort::ServerOrder => "server order",
+                RoomSort::Name => "name",
+                RoomSort::Members => "member count",
+                RoomSort::Newest => "newest",
+            };
+            let filter_block = Block::default()
+                .title(format!("Filter ({}, sorted by {})", kind_filter, sort))
+                .borders(Borders::ALL)
+                .border_style(if app.room_filter_editing {
+                    app.theme.focus
+                } else {
+                    Style::default()
+                });
+            let filter = Paragraph::new(format!("/{}", app.room_filter)).block(filter_block);
+            frame.render_widget(filter, area_chunks[1]);
 
-            let height = area_chunks[1].height as usize;
+            let rooms = app.filtered_rooms();
+
+            let height = area_chunks[2].height as usize;
             let mut room_per_page = height / ROOM_LIST_HEIGHT;
             if room_per_page == 0 {
                 room_per_page = 1;
             }
 
             let start_idx = (app.room_idx / room_per_page) * room_per_page;
-            let end_idx = (start_idx + room_per_page).min(app.room_lst.len());
+            let end_idx = (start_idx + room_per_page).min(rooms.len());
 
             let constraints = vec![
                 vec![Constraint::Fill(1)],
-                vec![Constraint::Length(ROOM_LIST_HEIGHT as u16); end_idx - start_idx],
+                vec![
+                    Constraint::Length(ROOM_LIST_HEIGHT as u16);
+                    end_idx.saturating_sub(start_idx)
+                ],
                 vec![Constraint::Fill(1)],
             ]
             .concat();
@@ -335,42 +535,95 @@ pub fn ui(
             let room_layout = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints(constraints)
-                .split(area_chunks[1]);
+                .split(area_chunks[2]);
 
             for idx in start_idx..end_idx {
-                let cur_room = app.room_lst[idx].clone();
-                let title = format!("{}{}", if cur_room.2 { "🔑" } else { "" }, cur_room.0);
+                let cur_room = rooms[idx].clone();
+                let title = format!(
+                    "{}{}",
+                    if cur_room.has_password { "🔑" } else { "" },
+                    cur_room.id
+                );
                 let mut room_block = Block::default().borders(Borders::ALL).title(title);
                 if idx == app.room_idx {
-                    room_block = room_block.border_style(Style::default().fg(Color::LightYellow));
+                    room_block = room_block.border_style(app.theme.focus);
+                }
+
+                let mut room_lines = vec![Line::from(cur_room.name)];
+                if let Some(topic) = cur_room.topic {
+                    room_lines.push(Line::styled(
+                        topic,
+                        Style::default().add_modifier(Modifier::ITALIC),
+                    ));
+                }
+                let mut metadata = vec![];
+                if let Some(member_count) = cur_room.member_count {
+                    metadata.push(format!(
+                        "{} member{}",
+                        member_count,
+                        if member_count == 1 { "" } else { "s" }
+                    ));
+                }
+                if let Some(creator) = cur_room.creator {
+                    metadata.push(format!("by {}", creator));
+                }
+                if let Some(created_at) = cur_room.created_at {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |x| x.as_secs());
+                    metadata.push(format!(
+                        "created {} ago",
+                        format_duration(now.saturating_sub(created_at))
+                    ));
                 }
-                let room = Paragraph::new(cur_room.1)
+                if !metadata.is_empty() {
+                    room_lines.push(Line::styled(metadata.join(" · "), app.theme.dim));
+                }
+
+                let room = Paragraph::new(room_lines)
                     .block(room_block)
                     .alignment(Alignment::Center);
 
                 frame.render_widget(room, room_layout[idx - start_idx + 1]);
+                click_areas
+                    .rooms
+                    .push((room_layout[idx - start_idx + 1], idx));
+            }
+
+            if rooms.is_empty() {
+                let no_match =
+                    Paragraph::new("No rooms match the filter").alignment(Alignment::Center);
+                frame.render_widget(
+                    no_match,
+                    centered_rect_with_constant_size(area_chunks[2].width, 1, area_chunks[2]),
+                );
             }
 
-            let cur_page = (app.room_idx / room_per_page) + 1;
-            let mut total_page = (app.room_lst.len() / room_per_page);
-            if app.room_lst.len() % room_per_page != 0 {
+            let cur_page = if rooms.is_empty() {
+                0
+            } else {
+                (app.room_idx / room_per_page) + 1
+            };
+            let mut total_page = rooms.len() / room_per_page;
+            if rooms.len() % room_per_page != 0 {
                 total_page += 1;
             }
 
             let page_display =
                 Paragraph::new(format!("{}/{}", cur_page, total_page)).alignment(Alignment::Center);
-            frame.render_widget(page_display, area_chunks[2]);
+            frame.render_widget(page_display, area_chunks[3]);
 
             match &app.join_room_error {
                 Some(error) => {
                     let err_block = Block::default()
                         .borders(Borders::ALL)
-                        .border_style(Style::default().fg(Color::Red));
+                        .border_style(app.theme.error);
                     match error {
                         JoinRoomError::RoomNotFound => {
-                            let err = Paragraph::new(
-                                "This room doesn't exist anymore, please reload this page with 'r'",
-                            )
+                            let err = Paragraph::new(format!(
+                                "This room doesn't exist anymore, please reload this page with {}",
+                                app.keymap.keys(KeyContext::RoomList, Action::ReloadRooms)
+                            ))
                             .block(err_block);
 
                             frame.render_widget(err, area_chunks[0]);
@@ -395,16 +648,23 @@ pub fn ui(
                 .split(frame.area());
 
             let instructions_block = Block::default().borders(Borders::ALL).title("Instructions");
-            let instructions = Paragraph::new("Enter = confirm, Esc = back to last page")
-                .block(instructions_block);
+            let keys = |action| app.keymap.keys(KeyContext::PasswordForm, action);
+            let instructions = Paragraph::new(format!(
+                "{} = confirm, {} = show/hide password, {} = back to last page",
+                keys(Action::Submit),
+                keys(Action::TogglePassword),
+                keys(Action::Back)
+            ))
+            .block(instructions_block);
             frame.render_widget(instructions, area_chunks[2]);
 
             let password_area = centered_rect_with_constant_size(40, 3, area_chunks[1]);
             let password_block = Block::default()
                 .title("Please enter the password of this room")
                 .borders(Borders::ALL)
-                .style(Style::default().bg(Color::LightYellow).fg(Color::Black));
-            let password = Paragraph::new(app.check_passwork.clone()).block(password_block);
+                .style(app.theme.active);
+            let password = Paragraph::new(mask_password(&app.check_passwork, app.show_password))
+                .block(password_block);
 
             frame.render_widget(password, password_area);
 
@@ -412,72 +672,230 @@ pub fn ui(
                 Some(error) => {
                     let err_block = Block::default()
                         .borders(Borders::ALL)
-                        .border_style(Style::default().fg(Color::Red));
+                        .border_style(app.theme.error);
 
                     match error {
                         JoinRoomError::RoomNotFound => {
                             let err = Paragraph::new("This room doesn't exist anymore, please go back to last page and reload").block(err_block);
                             frame.render_widget(err, area_chunks[0]);
                         }
-                        JoinRoomError::WrongPassword => {
-                            let err = Paragraph::new("Wrong password").block(err_block);
+                        error => {
+                            let err = Paragraph::new(join_room_error_msg(error)).block(err_block);
                             frame.render_widget(err, area_chunks[0]);
                         }
-                        _ => {}
                     }
                 }
                 None => {}
             }
         }
         CurrentScreen::Chat => {
-            let chunks = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints([Constraint::Percentage(80), Constraint::Fill(1)])
-                .split(frame.area());
-
-            let right_chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([Constraint::Fill(2), Constraint::Fill(1)])
-                .split(chunks[1]);
+            let area = frame.area();
+            let member_focused = matches!(app.chat_room_mode, ChatRoomMode::MemberList);
+            // Narrow terminals move the side panel under the chat when they are tall enough,
+            // otherwise it only shows up while the member list is focused
+            let (chat_area, side_area, stacked) = if area.width >= SIDEBAR_MIN_WIDTH {
+                let chunks = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Percentage(80), Constraint::Fill(1)])
+                    .split(area);
+                (chunks[0], chunks[1], false)
+            } else if area.height >= STACKED_MIN_HEIGHT {
+                let chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([
+                        Constraint::Fill(1),
+                        Constraint::Length(STACKED_PANEL_HEIGHT),
+                    ])
+                    .split(area);
+                (chunks[0], chunks[1], true)
+            } else if member_focused {
+                let chunks = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([
+                        Constraint::Fill(1),
+                        Constraint::Length(COLLAPSED_PANEL_WIDTH),
+                    ])
+                    .split(area);
+                (chunks[0], chunks[1], false)
+            } else {
+                (area, Rect::new(area.right(), area.y, 0, area.height), false)
+            };
+
+            let right_chunks = if stacked {
+                Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([
+                        Constraint::Fill(1),
+                        Constraint::Length(if member_focused { 28 } else { 0 }),
+                        Constraint::Fill(1),
+                    ])
+                    .split(side_area)
+            } else {
+                Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([
+                        Constraint::Fill(2),
+                        Constraint::Length(if member_focused { 6 } else { 0 }),
+                        Constraint::Fill(1),
+                    ])
+                    .split(side_area)
+            };
 
             let member_block = Block::default()
                 .title("Room members")
                 .borders(Borders::ALL)
-                .style(Style::default());
+                .style(Style::default())
+                .border_style(if member_focused {
+                    app.theme.input_focus
+                } else {
+                    Style::default()
+                });
             let mut members = Vec::<ListItem>::new();
 
-            for member in chat_room_member.into_iter() {
+            let self_muted = chat_room_member
+                .iter()
+                .any(|member| member.name == app.username && member.muted);
+            let member_idx = app.member_idx.min(chat_room_member.len().saturating_sub(1));
+            for (idx, member) in chat_room_member.iter().enumerate() {
+                let mut member_style = if member.name == app.username {
+                    app.theme
+                        .member
+                        .fg(app.theme.own_msg.fg.unwrap_or(Color::Reset))
+                } else {
+                    match app.theme.name_color(&member.name) {
+                        Some(color) => app.theme.member.fg(color),
+                        None => app.theme.member,
+                    }
+                };
+                if ignored_users.contains(&member.name) {
+                    member_style = member_style.patch(app.theme.dim);
+                }
+                if member_focused && idx == member_idx {
+                    member_style = member_style.patch(app.theme.member_selected);
+                }
                 members.push(ListItem::new(Line::from(Span::styled(
-                    format!("{: <25}", member),
-                    Style::default().fg(Color::Green),
+                    format!(
+                        "{}{}{}",
+                        match member.role {
+                            MemberRole::Owner => "★ ",
+                            MemberRole::Member => "  ",
+                        },
+                        member.name,
+                        if member.muted { " (muted)" } else { "" }
+                    ),
+                    member_style,
                 ))));
             }
 
+            let inner_area = member_block.inner(right_chunks[0]);
+            for idx in 0..chat_room_member.len().min(inner_area.height as usize) {
+                let row = Rect::new(inner_area.x, inner_area.y + idx as u16, inner_area.width, 1);
+                click_areas.members.push((row, idx));
+            }
             let list = List::new(members).block(member_block);
             frame.render_widget(list, right_chunks[0]);
 
+            if member_focused {
+                if let Some(member) = chat_room_member.get(member_idx) {
+                    let details_block = Block::default().borders(Borders::ALL).title("Details");
+                    let mut role = match member.role {
+                        MemberRole::Owner => "Owner",
+                        MemberRole::Member => "Member",
+                    }
+                    .to_string();
+                    if member.muted {
+                        role += ", muted";
+                    }
+                    let joined = match member.joined_at {
+                        Some(time) => format!("{} ago", format_elapsed(time)),
+                        None => "before you".to_string(),
+                    };
+                    let idle = match member.last_active {
+                        Some(time) => format_elapsed(time),
+                        None => "unknown".to_string(),
+                    };
+                    let details = Paragraph::new(vec![
+                        Line::from(format!("Name: {}", member.name)),
+                        Line::from(format!("Role: {}", role)),
+                        Line::from(format!("Joined: {}", joined)),
+                        Line::from(format!("Idle: {}", idle)),
+                    ])
+                    .block(details_block);
+                    frame.render_widget(details, right_chunks[1]);
+                }
+            }
+
             let instruction_block = Block::default().borders(Borders::ALL).title("Instructions");
-            let ins_inner_area = instruction_block.inner(right_chunks[1]);
+            let ins_inner_area = instruction_block.inner(right_chunks[2]);
             let (ins_width, ins_height) = (ins_inner_area.width, ins_inner_area.height);
             match app.chat_room_mode {
                 // Todo
                 ChatRoomMode::Normal => {
+                    let keys = |action| app.keymap.keys(KeyContext::ChatNormal, action);
+                    let normal_mode_instruction = [
+                        format!("{} => switch to input mode", keys(Action::EnterInput)),
+                        format!("{} => exit the room", keys(Action::LeaveRoom)),
+                        format!("{} => focus the member list", keys(Action::FocusMembers)),
+                        format!("{} => list all keys and commands", keys(Action::Help)),
+                    ];
                     let instructions = fit_instructions_into_block(
-                        &NORMAL_MODE_INSTRUCTION,
+                        &normal_mode_instruction.iter().map(String::as_str).collect(),
                         ins_width,
                         ins_height,
                     );
                     frame.render_widget(
                         List::new(instructions).block(instruction_block),
-                        right_chunks[1],
+                        right_chunks[2],
                     );
                 }
                 ChatRoomMode::Input => {
-                    let instructions =
-                        fit_instructions_into_block(&INPUT_MODE_INSTRUCTION, ins_width, ins_height);
+                    let keys = |action| app.keymap.keys(KeyContext::ChatInput, action);
+                    let input_mode_instruction = [
+                        format!("{} => exit input mode", keys(Action::Back)),
+                        format!(
+                            "{} => complete usernames and commands",
+                            keys(Action::Complete)
+                        ),
+                    ]
+                    .into_iter()
+                    .chain(
+                        COMMAND_HELP
+                            .iter()
+                            .map(|(usage, description)| format!("'{}' => {}", usage, description)),
+                    )
+                    .collect::<Vec<String>>();
+                    let instructions = fit_instructions_into_block(
+                        &input_mode_instruction.iter().map(String::as_str).collect(),
+                        ins_width,
+                        ins_height,
+                    );
                     frame.render_widget(
                         List::new(instructions).block(instruction_block),
-                        right_chunks[1],
+                        right_chunks[2],
+                    );
+                }
+                ChatRoomMode::MemberList => {
+                    let keys = |action| app.keymap.keys(KeyContext::MemberList, action);
+                    let member_list_instruction = [
+                        format!(
+                            "{}/{} => select a member",
+                            keys(Action::Up),
+                            keys(Action::Down)
+                        ),
+                        format!("{} => private message", keys(Action::PrivateMessage)),
+                        format!("{} => mention", keys(Action::Mention)),
+                        format!("{} => ignore/unignore", keys(Action::ToggleIgnore)),
+                        format!("{} => kick (owner only)", keys(Action::Kick)),
+                        format!("{} => back to normal mode", keys(Action::Back)),
+                    ];
+                    let instructions = fit_instructions_into_block(
+                        &member_list_instruction.iter().map(String::as_str).collect(),
+                        ins_width,
+                        ins_height,
+                    );
+                    frame.render_widget(
+                        List::new(instructions).block(instruction_block),
+                        right_chunks[2],
                     );
                 }
             }
@@ -485,69 +903,212 @@ pub fn ui(
             let left_chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints([Constraint::Percentage(100), Constraint::Min(4)])
-                .split(chunks[0]);
+                .split(chat_area);
 
+            let mut chat_title = format!(
+                "Room ID: {}, Room Name: {}",
+                app.room_id.clone(),
+                app.room_name.clone()
+            );
+            if let Some(invite_link) = &app.invite_link {
+                chat_title += &format!(", Invite: {}", invite_link);
+            }
+            if app.notified_mentions > 0 {
+                chat_title += &format!(", Mentions: {}", app.notified_mentions);
+            }
+            // Scrolled back messages are cut off the end so older ones fill the screen
+            let scroll = app
+                .chat_scroll
+                .min(chat_room_record.len().saturating_sub(1));
+            chat_room_record.truncate(chat_room_record.len() - scroll);
+            if scroll > 0 {
+                chat_title += &format!(
+                    ", {} newer message{} below",
+                    scroll,
+                    if scroll == 1 { "" } else { "s" }
+                );
+            }
             let chat_block = Block::default()
-                .title(format!(
-                    "Room ID: {}, Room Name: {}",
-                    app.room_id.clone(),
-                    app.room_name.clone()
-                ))
+                .title(chat_title)
                 .borders(Borders::ALL)
                 .style(Style::default());
 
-            let chat_inner_area = chat_block.inner(left_chunks[0]);
-            let (width, height) = (chat_inner_area.width, chat_inner_area.height);
+            let chat_inner_chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(if room_topic.is_empty() { 0 } else { 1 }),
+                    Constraint::Fill(1),
+                ])
+                .split(chat_block.inner(left_chunks[0]));
+            frame.render_widget(chat_block, left_chunks[0]);
+
+            if !room_topic.is_empty() {
+                let topic = Paragraph::new(Line::from(vec![
+                    Span::styled("Topic: ", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(room_topic),
+                ]))
+                .style(app.theme.bar);
+                frame.render_widget(topic, chat_inner_chunks[0]);
+            }
+
+            let (width, height) = (chat_inner_chunks[1].width, chat_inner_chunks[1].height);
             let messages = fit_msg_into_chat_block(
                 chat_room_record,
                 width as usize,
                 height as usize,
                 &app.username,
+                &ignored_users,
+                &app.room.highlight_rules,
+                &app.theme,
             );
 
-            let message_list = List::new(messages).block(chat_block);
-            frame.render_widget(message_list, left_chunks[0]);
+            let message_list = List::new(messages);
+            frame.render_widget(message_list, chat_inner_chunks[1]);
+            click_areas.chat_history = Some(chat_inner_chunks[1]);
+            click_areas.chat_input = Some(left_chunks[1]);
 
             let input_block = Block::default()
-                .title("Input")
+                .title(if self_muted { "Input (muted)" } else { "Input" })
                 .borders(Borders::ALL)
                 .style(Style::default())
                 .border_style(match app.chat_room_mode {
-                    ChatRoomMode::Normal => Style::default(),
-                    ChatRoomMode::Input => Style::default().fg(Color::Blue),
+                    ChatRoomMode::Normal | ChatRoomMode::MemberList => Style::default(),
+                    ChatRoomMode::Input => app.theme.input_focus,
                 });
             let inner_area = input_block.inner(left_chunks[1]);
             let input_width = inner_area.width as usize;
 
-            let input_clone = app.input.clone();
-            if input_width < input_clone.len() {
-                let input_text = Paragraph::new(
-                    &input_clone[input_clone.len() - input_width..input_clone.len()],
-                )
+            let input_clone = match app.input.strip_prefix("/password set ") {
+                Some(password) => {
+                    format!("/password set {}", "*".repeat(password.chars().count()))
+                }
+                None => app.input.clone(),
+            };
+            // Only the end of a long input fits. Counted in chars, a byte offset could land
+            // inside a multi-byte character.
+            let hidden = input_clone.chars().count().saturating_sub(input_width);
+            let input_text = Paragraph::new(input_clone.chars().skip(hidden).collect::<String>())
                 .block(input_block);
-                frame.render_widget(input_text, left_chunks[1]);
-            } else {
-                let input_text = Paragraph::new(input_clone).block(input_block);
-                frame.render_widget(input_text, left_chunks[1]);
-            }
+            frame.render_widget(input_text, left_chunks[1]);
 
             if app.exiting {
-                let popup_dialog_area = centered_rect_with_constant_size(80, 5, frame.area());
-                frame.render_widget(Clear, popup_dialog_area);
-                let pupup_dialog_block = Block::default()
-                    .padding(Padding::vertical(1))
-                    .title("Press 'y' to exit the room, or press 'n' to cancel")
-                    .title_alignment(Alignment::Center)
-                    .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Red));
-                let popup_dialog = Paragraph::new("Are you sure you want to leave the chat room?")
-                    .alignment(Alignment::Center)
-                    .block(pupup_dialog_block);
-                frame.render_widget(popup_dialog, popup_dialog_area);
+                render_dialog(
+                    frame,
+                    format!(
+                        "Press {} to exit the room, or press {} to cancel",
+                        app.keymap.keys(KeyContext::ExitConfirm, Action::Confirm),
+                        app.keymap.keys(KeyContext::ExitConfirm, Action::Back)
+                    ),
+                    vec!["Are you sure you want to leave the chat room?".to_string()],
+                    app.theme.error,
+                );
             }
         }
         CurrentScreen::Exiting => {}
     }
+
+    if app.show_help {
+        let help_area = help_area(frame.area());
+        frame.render_widget(Clear, help_area);
+        let lines = help_lines(app);
+        let max_scroll = (lines.len() as u16).saturating_sub(help_area.height.saturating_sub(2));
+        let help_block = Block::default()
+            .title(format!(
+                "Help ({} = scroll, {} = close)",
+                [
+                    app.keymap.keys(KeyContext::HelpOverlay, Action::Up),
+                    app.keymap.keys(KeyContext::HelpOverlay, Action::Down),
+                ]
+                .join("/"),
+                app.keymap.keys(KeyContext::HelpOverlay, Action::Back)
+            ))
+            .borders(Borders::ALL)
+            .border_style(app.theme.input_focus);
+        let help = Paragraph::new(lines)
+            .scroll((app.help_scroll.min(max_scroll), 0))
+            .block(help_block);
+        frame.render_widget(help, help_area);
+    }
+    click_areas
+}
+
+pub fn help_area(area: Rect) -> Rect {
+    centered_rect(80, 80, area)
+}
+
+// Keys for whatever the overlay was opened on, plus the slash commands inside a chat room
+pub fn help_lines(app: &App) -> Vec<Line<'static>> {
+    let context = app.key_context();
+    let mut contexts = vec![context];
+    // The chat room modes are one press apart, so they are all listed
+    if context == KeyContext::ChatNormal
+        || context == KeyContext::MemberList
+        || context == KeyContext::ChatInput
+    {
+        for other in [
+            KeyContext::ChatNormal,
+            KeyContext::ChatInput,
+            KeyContext::MemberList,
+        ] {
+            if other != context {
+                contexts.push(other);
+            }
+        }
+    }
+
+    let heading = Style::default().add_modifier(Modifier::BOLD);
+    let mut lines = vec![];
+    for context in contexts {
+        let bindings = app.keymap.bindings(context);
+        let width = bindings
+            .iter()
+            .map(|(_, keys)| keys.len())
+            .max()
+            .unwrap_or(0);
+        lines.push(Line::styled(
+            format!("Keys in the {}", context.as_str()),
+            heading,
+        ));
+        for (action, keys) in bindings {
+            lines.push(Line::from(vec![
+                Span::styled(format!("  {:<width$}  ", keys), app.theme.focus),
+                Span::raw(action.description()),
+            ]));
+        }
+        // Digits aren't a keymap action, so they are listed by hand
+        if context == KeyContext::RecentRooms {
+            lines.push(Line::from(vec![
+                Span::styled(format!("  {:<width$}  ", "1-9"), app.theme.focus),
+                Span::raw("rejoin the room with that number"),
+            ]));
+        }
+        if matches!(
+            context,
+            KeyContext::Form | KeyContext::PasswordForm | KeyContext::ChatInput
+        ) {
+            lines.push(Line::styled(
+                "  Anything else is typed into the input box",
+                app.theme.dim,
+            ));
+        }
+        lines.push(Line::default());
+    }
+
+    if let CurrentScreen::Chat = app.current_screen {
+        let width = COMMAND_HELP
+            .iter()
+            .map(|(usage, _)| usage.len())
+            .max()
+            .unwrap_or(0);
+        lines.push(Line::styled("Chat commands", heading));
+        for (usage, description) in COMMAND_HELP {
+            lines.push(Line::from(vec![
+                Span::styled(format!("  {:<width$}  ", usage), app.theme.focus),
+                Span::raw(*description),
+            ]));
+        }
+    }
+    lines
 }
 
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -572,7 +1133,68 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
         .split(popup_layout[1])[1] // Return the middle chunk
 }
 
+// Dialogs shrink to fit narrower terminals
+const DIALOG_WIDTH: u16 = 80;
+
+// The key hint stays in the title while it fits and joins the text otherwise,
+// and the dialog grows taller when the text has to wrap
+fn render_dialog(frame: &mut Frame, hint: String, mut lines: Vec<String>, border_style: Style) {
+    let area = frame.area();
+    let width = DIALOG_WIDTH.min(area.width);
+    let inner_width = width.saturating_sub(2) as usize;
+    let mut block = Block::default()
+        .padding(Padding::vertical(1))
+        .borders(Borders::ALL)
+        .border_style(border_style);
+    if hint.chars().count() <= inner_width {
+        block = block.title(hint).title_alignment(Alignment::Center);
+    } else {
+        lines.push(hint);
+    }
+    let height = lines
+        .iter()
+        .map(|line| wrapped_line_count(line, inner_width))
+        .sum::<usize>() as u16
+        + 4;
+
+    let dialog_area = centered_rect_with_constant_size(width, height, area);
+    frame.render_widget(Clear, dialog_area);
+    let dialog = Paragraph::new(lines.into_iter().map(Line::from).collect::<Vec<Line>>())
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: true })
+        .block(block);
+    frame.render_widget(dialog, dialog_area);
+}
+
+// Lines the text takes up when word wrapped at the given width
+fn wrapped_line_count(text: &str, width: usize) -> usize {
+    if width == 0 {
+        return 1;
+    }
+    let mut lines = 1;
+    let mut line_len = 0;
+    for word in text.split_whitespace() {
+        let word_len = word.chars().count();
+        if line_len == 0 {
+            line_len = word_len;
+        } else if line_len + 1 + word_len <= width {
+            line_len += 1 + word_len;
+        } else {
+            lines += 1;
+            line_len = word_len;
+        }
+        // Words longer than a line get broken up
+        while line_len > width {
+            lines += 1;
+            line_len -= width;
+        }
+    }
+    lines
+}
+
+// Never bigger than the area it is centered in
 fn centered_rect_with_constant_size(size_x: u16, size_y: u16, r: Rect) -> Rect {
+    let (size_x, size_y) = (size_x.min(r.width), size_y.min(r.height));
     let popup_layout = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
@@ -614,30 +1236,61 @@ fn fit_msg_into_chat_block<'a>(
     width: usize,
     height: usize,
     username: &str,
+    ignored_users: &[String],
+    highlight_rules: &[Regex],
+    theme: &Theme,
 ) -> VecDeque<ListItem<'a>> {
     let mut res: VecDeque<ListItem> = VecDeque::new();
+    // Consecutive messages from ignored users collapse into a single placeholder line
+    let mut hidden = 0;
     while res.len() < height && !msg_vec.is_empty() {
         let msg = msg_vec.pop_back().unwrap();
+        let sender = extract_username(&msg).or_else(|| private_msg_sender(&msg));
+        if sender.is_some_and(|name| ignored_users.contains(&name)) {
+            hidden += 1;
+            continue;
+        }
+        if hidden > 0 {
+            res.push_front(hidden_msg_item(hidden, theme));
+            hidden = 0;
+            if res.len() >= height {
+                break;
+            }
+        }
+
         let user_msg = match extract_username(&msg) {
             Some(name) => {
                 if name.as_str() == username {
                     MsgType::UserMsg
+                } else if mentions(&msg[name.len() + 1..], username) {
+                    MsgType::MentionMsg
+                } else if highlight_rules
+                    .iter()
+                    .any(|re| re.is_match(&msg[name.len() + 1..]))
+                {
+                    MsgType::HighlightMsg
                 } else {
                     MsgType::OtherMsg
                 }
             }
+            None if is_private_msg(&msg) => MsgType::PrivateMsg,
             None => MsgType::SystemMsg,
         };
 
+        // Other people's names get their own stable color so busy rooms are easier to follow
+        let name_color = match user_msg {
+            MsgType::OtherMsg | MsgType::MentionMsg | MsgType::HighlightMsg => {
+                extract_username(&msg).and_then(|name| theme.name_color(&name).map(|c| (name, c)))
+            }
+            _ => None,
+        };
+
         if msg.len() <= width {
-            res.push_front(ListItem::new(Line::from(Span::styled(
+            res.push_front(ListItem::new(msg_line(
                 msg,
-                match user_msg {
-                    MsgType::UserMsg => Style::default().fg(Color::LightYellow),
-                    MsgType::OtherMsg => Style::default().fg(Color::LightGreen),
-                    MsgType::SystemMsg => Style::default(),
-                },
-            ))));
+                msg_style(&user_msg, theme),
+                name_color.as_ref(),
+            )));
         } else {
             let split_msg = msg.split(" ").collect::<Vec<&str>>();
 
@@ -660,22 +1313,102 @@ fn fit_msg_into_chat_block<'a>(
             if res.len() + tmp_vec.len() > height {
                 break;
             } else {
-                while !tmp_vec.is_empty() {
-                    res.push_front(ListItem::new(Line::from(Span::styled(
-                        tmp_vec.pop().unwrap(),
-                        match user_msg {
-                            MsgType::UserMsg => Style::default().fg(Color::LightYellow),
-                            MsgType::OtherMsg => Style::default().fg(Color::LightGreen),
-                            MsgType::SystemMsg => Style::default(),
-                        },
-                    ))));
+                for (idx, line) in tmp_vec.into_iter().enumerate().rev() {
+                    res.push_front(ListItem::new(msg_line(
+                        line,
+                        msg_style(&user_msg, theme),
+                        name_color.as_ref().filter(|_| idx == 0),
+                    )));
                 }
             }
         }
     }
+    if hidden > 0 && res.len() < height {
+        res.push_front(hidden_msg_item(hidden, theme));
+    }
     res
 }
 
+// Colors the leading "name:" of a line when a name color is given
+fn msg_line<'a>(line: String, style: Style, name_color: Option<&(String, Color)>) -> Line<'a> {
+    match name_color {
+        Some((name, color)) if line.starts_with(&format!("{}:", name)) => {
+            let rest = line[name.len() + 1..].to_string();
+            Line::from(vec![
+                Span::styled(format!("{}:", name), style.fg(*color)),
+                Span::styled(rest, style),
+            ])
+        }
+        _ => Line::from(Span::styled(line, style)),
+    }
+}
+
+fn msg_style(msg_type: &MsgType, theme: &Theme) -> Style {
+    match msg_type {
+        MsgType::UserMsg => theme.own_msg,
+        MsgType::OtherMsg => theme.other_msg,
+        MsgType::SystemMsg => theme.system_msg,
+        MsgType::PrivateMsg => theme.private_msg,
+        MsgType::MentionMsg => theme.mention_msg,
+        MsgType::HighlightMsg => theme.highlight_msg,
+    }
+}
+
+fn join_room_error_msg(error: &JoinRoomError) -> String {
+    match error {
+        JoinRoomError::InvalidUsername => {
+            "Username should not contain special characters or whitespace".to_string()
+        }
+        JoinRoomError::RoomIdLengthError => "Invalid Room Id or invite link format".to_string(),
+        JoinRoomError::RoomNotFound => "Room not found".to_string(),
+        JoinRoomError::InvalidUsernameLength => {
+            "Username's length should be between 1 and 50".to_string()
+        }
+        JoinRoomError::GetRoomListFailed => "Failed to get room list".to_string(),
+        JoinRoomError::WrongPassword => "Wrong password".to_string(),
+        JoinRoomError::PasswordRequired => "This room needs a password".to_string(),
+        JoinRoomError::ZeroRooms => "There is no room to join".to_string(),
+        JoinRoomError::UsernameTaken => {
+            "This username is already taken in that room, please choose another one".to_string()
+        }
+        JoinRoomError::OtherServer(host) => format!(
+            "This invite link is for {}, start the client with that server to join",
+            host
+        ),
+    }
+}
+
+fn hidden_msg_item<'a>(count: usize, theme: &Theme) -> ListItem<'a> {
+    ListItem::new(Line::from(Span::styled(
+        format!(
+            "{} hidden message{}",
+            count,
+            if count == 1 { "" } else { "s" }
+        ),
+        theme.dim.add_modifier(Modifier::ITALIC),
+    )))
+}
+
+fn mask_password(password: &str, show: bool) -> String {
+    if show {
+        password.to_string()
+    } else {
+        "•".repeat(password.chars().count())
+    }
+}
+
+fn format_elapsed(since: Instant) -> String {
+    format_duration(Instant::now().duration_since(since).as_secs())
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h", secs / 3600),
+    }
+}
+
 fn fit_instructions_into_block<'a>(
     instructions: &Vec<&str>,
     width: u16,
@@ -728,3 +1461,20 @@ fn extract_username(hay: &str) -> Option<String> {
     }
     None
 }
+
+fn is_private_msg(hay: &str) -> bool {
+    static RE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"^\[DM (from|to) [^!@#$%\^\&\*\(\)\+=\[\]\{\}:;'"/<>|\\`~\?,\.\s]+\] "#)
+            .unwrap()
+    });
+    RE.is_match(hay)
+}
+
+fn private_msg_sender(hay: &str) -> Option<String> {
+    static RE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"^\[DM from ([^!@#$%\^\&\*\(\)\+=\[\]\{\}:;'"/<>|\\`~\?,\.\s]+)\] "#).unwrap()
+    });
+    RE.captures(hay)
+        .and_then(|cap| cap.get(1))
+        .map(|name| name.as_str().to_string())
+}
@@ -1,3 +1,6 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
 pub(crate) fn read_buf(buf: &[u8]) -> String {
     match buf.iter().enumerate().find(|(_, x)| **x == 0) {
         Some((ind, _)) => {
@@ -12,4 +15,77 @@ pub(crate) fn clear_buf(buf: &mut [u8]) {
     for i in 0..size {
         buf[i] = 0
     }
-}
\ No newline at end of file
+}
+
+// True when `msg` contains "@username" as a standalone word
+pub(crate) fn mentions(msg: &str, username: &str) -> bool {
+    let tag = format!("@{}", username);
+    msg.match_indices(&tag).any(|(idx, _)| {
+        let before = msg[..idx].chars().next_back();
+        let after = msg[idx + tag.len()..].chars().next();
+        before.is_none_or(|c| c.is_whitespace())
+            && after.is_none_or(|c| c.is_whitespace() || ",.!?:;".contains(c))
+    })
+}
+
+// Case-insensitive subsequence match, so "gnrl" matches "General"
+pub(crate) fn fuzzy_match(hay: &str, needle: &str) -> bool {
+    let mut hay_chars = hay.chars().flat_map(|c| c.to_lowercase());
+    needle
+        .chars()
+        .flat_map(|c| c.to_lowercase())
+        .all(|n| hay_chars.any(|h| h == n))
+}
+
+// FNV-1a, unlike std's hasher it is guaranteed to give the same value across runs and versions
+pub(crate) fn stable_hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub(crate) fn is_valid_string(s: &str) -> bool {
+    static USERNAME_RESTRICT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"([!@#$%\^\&\*\(\)\+=\[\]\{\}:;'"/<>|\\`~\?,\.\s]+)"#).unwrap());
+
+    !USERNAME_RESTRICT.is_match(s)
+}
+
+pub(crate) fn is_valid_string_with_whitespace(s: &str) -> bool {
+    static USERNAME_RESTRICT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"([@#$%\^\&\(\)\+=\[\]\{\}:;'"/|\\`~,\.]+)"#).unwrap());
+
+    !USERNAME_RESTRICT.is_match(s)
+}
+
+pub(crate) fn is_valid_room_id(s: &str) -> bool {
+    static ROOM_ID_FORMAT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"^[A-Za-z0-9\-]{1,36}$"#).unwrap());
+
+    ROOM_ID_FORMAT.is_match(s)
+}
+
+pub(crate) fn is_valid_invite_code(s: &str) -> bool {
+    static INVITE_CODE_FORMAT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"^[A-Za-z0-9]{1,64}$"#).unwrap());
+
+    INVITE_CODE_FORMAT.is_match(s)
+}
+
+pub(crate) fn has_whitespace(s: &str) -> bool {
+    static USERNAME_RESTRICT: Lazy<Regex> = Lazy::new(|| Regex::new(r#"([\s]+)"#).unwrap());
+
+    USERNAME_RESTRICT.is_match(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_hash_is_fnv1a_64() {
+        assert_eq!(stable_hash(""), 0xcbf29ce484222325);
+        assert_eq!(stable_hash("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(stable_hash("foobar"), 0x85944171f73967e8);
+    }
+}